@@ -1,7 +1,24 @@
 mod basic_app;
+mod buffer;
+mod color;
+mod command;
+mod completion;
+mod config;
+mod entry;
+mod headless_app;
+mod history;
+mod input_line;
+mod mention;
+mod search;
+mod theme;
 mod tui_app;
+mod wrap;
 
 pub use basic_app::BasicApp;
+pub use color::Palette;
+pub use config::{Config, Headless, Membership, Notify};
+pub use headless_app::HeadlessApp;
+pub use theme::Themes;
 pub use tui_app::TuiApp;
 
 use crate::{client::ClientInput, error::Result, protocol::ServerCommand};
@@ -12,5 +29,10 @@ type Rx<T> = tokio::sync::mpsc::UnboundedReceiver<T>;
 /// Collect user inputs to `Client` and show data from `Client`
 pub trait App {
     /// This method will create and run async app tasks, and return immediately
-    fn start(input_tx: Tx<ClientInput>, msg_rx: Rx<ServerCommand>, name: &str) -> Result<()>;
+    fn start(
+        input_tx: Tx<ClientInput>,
+        msg_rx: Rx<ServerCommand>,
+        name: &str,
+        config: Config,
+    ) -> Result<()>;
 }
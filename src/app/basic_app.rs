@@ -1,6 +1,15 @@
+use chrono::{Local, TimeZone};
 use tokio::sync::mpsc;
+use unicode_width::UnicodeWidthStr;
 
-use crate::{client::ClientInput, error::*, protocol::ServerCommand};
+use super::command::{self, Command};
+use super::Config;
+use crate::{
+    client::ClientInput,
+    error::*,
+    message::{parse_markup, Message, Segment},
+    protocol::ServerCommand,
+};
 
 type Tx<T> = mpsc::UnboundedSender<T>;
 type Rx<T> = mpsc::UnboundedReceiver<T>;
@@ -9,18 +18,84 @@ type Rx<T> = mpsc::UnboundedReceiver<T>;
 pub struct BasicApp {}
 
 impl super::App for BasicApp {
-    fn start(input_tx: Tx<ClientInput>, mut msg_rx: Rx<ServerCommand>, name: &str) -> Result<()> {
-        println!("Joined as `{}`.", name);
+    fn start(
+        input_tx: Tx<ClientInput>,
+        mut msg_rx: Rx<ServerCommand>,
+        name: &str,
+        config: Config,
+    ) -> Result<()> {
+        // render the markup only for a human reading a terminal
+        let markup = termion::is_tty(&std::io::stdout());
+        let colors = !config.no_color;
 
-        let _in_task = tokio::spawn(async move {
+        println!("Joined as `{}`, send :help for the commands.", name);
+
+        // reading stdin blocks, so run it on a blocking thread instead of starving the runtime
+        let _in_task = tokio::task::spawn_blocking(move || {
             loop {
-                let input = {
+                // a line ending with `\` continues on the next one
+                let mut input = String::new();
+                loop {
                     let mut buf = String::new();
-                    std::io::stdin().read_line(&mut buf).unwrap();
-                    buf
+                    // leave at the end of the input, e.g. of a pipe
+                    if std::io::stdin().read_line(&mut buf).unwrap_or(0) == 0 {
+                        if !input.is_empty() {
+                            let _ = input_tx.send(ClientInput::Message(Message::Text(input)));
+                        }
+                        let _ = input_tx.send(ClientInput::Exit);
+                        return;
+                    }
+                    match buf.trim_end_matches(&['\r', '\n'][..]).strip_suffix('\\') {
+                        Some(line) => {
+                            input.push_str(line);
+                            input.push('\n');
+                        }
+                        None => {
+                            input.push_str(&buf);
+                            break;
+                        }
+                    }
+                }
+                let command = match command::parse(&input, false) {
+                    // send msg to client
+                    None => {
+                        input_tx
+                            .send(ClientInput::Message(Message::Text(input)))
+                            .unwrap();
+                        continue;
+                    }
+                    Some(Ok(command)) => command,
+                    Some(Err(e)) => {
+                        println!("{}", e);
+                        continue;
+                    }
+                };
+                let input = match command {
+                    Command::Help(name) => {
+                        for line in command::help(name.as_deref(), false) {
+                            println!("{}", line);
+                        }
+                        continue;
+                    }
+                    Command::Exit => {
+                        input_tx.send(ClientInput::Exit).unwrap();
+                        break;
+                    }
+                    Command::Nick(name) => ClientInput::SetName(name),
+                    Command::Join(room) if room == "lobby" => {
+                        println!("You are in #lobby");
+                        continue;
+                    }
+                    Command::Join(room) => {
+                        println!("No such room `#{}`, there's only #lobby", room);
+                        continue;
+                    }
+                    Command::Me(action) => ClientInput::Message(Message::Action(action)),
+                    Command::Msg(user, text) => ClientInput::Direct(user, Message::Text(text)),
+                    // only in the terminal UI
+                    Command::Clear | Command::Raw | Command::Theme(_) | Command::Fuck => continue,
                 };
-                // send msg to client
-                input_tx.send(ClientInput::Text(input)).unwrap();
+                input_tx.send(input).unwrap();
             }
         });
 
@@ -28,18 +103,33 @@ impl super::App for BasicApp {
             // recv command from client
             while let Some(command) = msg_rx.recv().await {
                 match command {
-                    ServerCommand::UserMessage(user, message) => {
-                        let msg = format!("[{}] {}", user, message);
-                        println!("{}", msg);
+                    ServerCommand::UserMessage(user, message, _) => {
+                        print_message(&user, &message, markup, colors);
+                    }
+                    ServerCommand::DirectMessage(from, to, message) => {
+                        print_message(&format!("{} -> {}", from, to), &message, markup, colors);
                     }
                     ServerCommand::ServerMessage(message) => {
-                        let msg = format!("<SERVER> {}", message);
-                        println!("{}", msg);
+                        println!("<SERVER> {}", indent_lines(message.text(), 9));
                     }
                     ServerCommand::UserList(users) => {
                         let msg = format!("<SERVER> Online users: {:?}", users);
                         println!("{}", msg);
                     }
+                    ServerCommand::UserJoined { user, time, .. } => {
+                        println!("<SERVER> [{}] {} joined", format_time(time), user);
+                    }
+                    ServerCommand::UserLeft { user, time, .. } => {
+                        println!("<SERVER> [{}] {} left", format_time(time), user);
+                    }
+                    ServerCommand::UserRenamed { old, new, time, .. } => {
+                        println!(
+                            "<SERVER> [{}] {} is now known as {}",
+                            format_time(time),
+                            old,
+                            new
+                        );
+                    }
                     ServerCommand::Error(message) => {
                         let msg = format!("<SERVER> Unknown: {}", message);
                         println!("{}", msg);
@@ -47,6 +137,27 @@ impl super::App for BasicApp {
                     ServerCommand::ServerName(name) => {
                         println!("<SERVER> Server's name is `{}`", name);
                     }
+                    ServerCommand::SearchResults(query, results) => {
+                        println!("<SERVER> {} results for `{}`", results.len(), query);
+                        for result in results {
+                            let time = Local.timestamp(result.time, 0);
+                            let prefix = format!(
+                                "  #{} [{}, {}] ",
+                                result.id,
+                                result.user,
+                                time.format("%Y-%m-%d %H:%M")
+                            );
+                            println!(
+                                "{}{}",
+                                prefix,
+                                indent_lines(&result.message.to_string(), prefix.width())
+                            );
+                        }
+                    }
+                    // negotiated by the client before the app starts
+                    ServerCommand::Encoding(_) => {}
+                    // only sent to linked servers
+                    ServerCommand::Relay(_) => {}
                 }
             }
         });
@@ -54,3 +165,60 @@ impl super::App for BasicApp {
         Ok(())
     }
 }
+
+/// Print a message of `who`, e.g. `[bob] hi`, `* bob waves` or `-bob- hi`
+pub(super) fn print_message(who: &str, message: &Message, markup: bool, colors: bool) {
+    let prefix = match message {
+        Message::Text(_) => format!("[{}] ", who),
+        Message::Action(_) => format!("* {} ", who),
+        Message::Notice(_) => format!("-{}- ", who),
+        Message::System(_) => "<SERVER> ".to_owned(),
+    };
+    let text = if markup {
+        to_ansi(&parse_markup(message.text()), colors)
+    } else {
+        message.text().to_owned()
+    };
+    println!("{}{}", prefix, indent_lines(&text, prefix.width()));
+}
+
+/// e.g. `12:00:00` for a unix timestamp in seconds
+fn format_time(time: i64) -> String {
+    Local.timestamp(time, 0).format("%H:%M:%S").to_string()
+}
+
+/// Indent the lines after the first one, to align them with the text after a prefix
+fn indent_lines(text: &str, indent: usize) -> String {
+    text.replace('\n', &format!("\n{}", " ".repeat(indent)))
+}
+
+/// Render the markup with ANSI escape codes, `colors` being used for code and urls
+fn to_ansi(segments: &[Segment], colors: bool) -> String {
+    segments
+        .iter()
+        .map(|segment| {
+            let markup = segment.markup;
+            let mut codes = Vec::new();
+            if markup.bold {
+                codes.push("1");
+            }
+            if markup.italic {
+                codes.push("3");
+            }
+            if markup.url {
+                codes.push("4");
+            }
+            if colors && markup.code {
+                codes.push("36");
+            }
+            if colors && markup.url {
+                codes.push("34");
+            }
+            if codes.is_empty() {
+                segment.text.clone()
+            } else {
+                format!("\x1b[{}m{}\x1b[0m", codes.join(";"), segment.text)
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,51 @@
+//! Message buffers of the conversations shown as tabs by `TuiApp`
+
+use std::fmt;
+
+use super::entry::Entry;
+use crate::message::User;
+
+/// A room or a direct conversation with a user
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Conversation {
+    Room(String),
+    Direct(User),
+}
+
+impl fmt::Display for Conversation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Conversation::Room(room) => write!(f, "#{}", room),
+            Conversation::Direct(user) => write!(f, "@{}", user),
+        }
+    }
+}
+
+/// Messages of a conversation, with the view state of its tab
+#[derive(Default, Debug)]
+pub struct Buffer {
+    pub messages: Vec<Entry>,
+    pub users: Vec<User>,
+    pub scroll: usize, // number of messages scrolled up from the bottom, 0 means pinned
+    pub new_below: usize, // number of messages arrived while scrolled up
+    pub unread: usize, // number of messages arrived while the tab is not shown
+}
+
+impl Buffer {
+    /// Add a message, the view is kept still if scrolled up
+    pub fn push(&mut self, entry: Entry, shown: bool) {
+        self.messages.push(entry);
+        if !shown {
+            self.unread += 1;
+        } else if self.scroll > 0 {
+            self.scroll += 1;
+            self.new_below += 1;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.messages.clear();
+        self.scroll = 0;
+        self.new_below = 0;
+    }
+}
@@ -0,0 +1,112 @@
+//! Stable colors of users
+
+use std::str::FromStr;
+
+use tui::style::{Color, Style};
+
+/// Colors to pick from for user names
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Palette {
+    #[default]
+    Default,
+    /// The Okabe-Ito palette, distinguishable with any color vision deficiency
+    Colorblind,
+}
+
+impl FromStr for Palette {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "default" => Ok(Palette::Default),
+            "colorblind" => Ok(Palette::Colorblind),
+            _ => Err(format!(
+                "unknown palette `{}`, expect default or colorblind",
+                s
+            )),
+        }
+    }
+}
+
+const DEFAULT_COLORS: &[Color] = &[
+    Color::Red,
+    Color::Green,
+    Color::Yellow,
+    Color::Blue,
+    Color::Magenta,
+    Color::Cyan,
+    Color::LightRed,
+    Color::LightGreen,
+    Color::LightYellow,
+    Color::LightBlue,
+    Color::LightMagenta,
+    Color::LightCyan,
+];
+
+const COLORBLIND_COLORS: &[Color] = &[
+    Color::Rgb(230, 159, 0),   // orange
+    Color::Rgb(86, 180, 233),  // sky blue
+    Color::Rgb(0, 158, 115),   // bluish green
+    Color::Rgb(240, 228, 66),  // yellow
+    Color::Rgb(0, 114, 178),   // blue
+    Color::Rgb(213, 94, 0),    // vermillion
+    Color::Rgb(204, 121, 167), // reddish purple
+];
+
+impl Palette {
+    /// The color of `user`, the same across runs and machines, and whatever the case of the name
+    pub fn user_color(self, user: &str) -> Color {
+        let colors = match self {
+            Palette::Default => DEFAULT_COLORS,
+            Palette::Colorblind => COLORBLIND_COLORS,
+        };
+        colors[(fnv1a(&user.to_lowercase()) % colors.len() as u64) as usize]
+    }
+}
+
+/// `std`'s hasher is not guaranteed to be stable, so use the simple FNV-1a
+fn fnv1a(s: &str) -> u64 {
+    s.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Drop the colors of a style, keeping its modifiers
+pub fn strip_colors(style: Style) -> Style {
+    Style {
+        fg: None,
+        bg: None,
+        ..style
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_is_stable() {
+        // reference values of 64-bit FNV-1a
+        assert_eq!(fnv1a(""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a("a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(Palette::Default.user_color("alice"), Color::LightCyan);
+        assert_eq!(Palette::Default.user_color("bob"), Color::Red);
+        assert_eq!(
+            Palette::Colorblind.user_color("alice"),
+            Color::Rgb(86, 180, 233)
+        );
+        assert_eq!(
+            Palette::Colorblind.user_color("bob"),
+            Color::Rgb(0, 158, 115)
+        );
+    }
+
+    #[test]
+    fn case_keeps_the_color() {
+        for palette in [Palette::Default, Palette::Colorblind] {
+            for (a, b) in [("alice", "Alice"), ("bob", "BOB"), ("zoë", "ZOË")] {
+                assert_eq!(palette.user_color(a), palette.user_color(b));
+            }
+        }
+    }
+}
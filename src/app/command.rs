@@ -0,0 +1,287 @@
+//! Client commands typed as `:name args`, declared once for both apps
+//!
+//! The usage of a command declares its arguments: `<arg>` is required, `[arg]` is optional,
+//! and a last `<args...>` takes the rest of the line. A line whose first word isn't the name
+//! of a command, e.g. `:)`, is no command and is sent as text.
+
+use crate::message::User;
+
+/// Declaration of a client command
+#[derive(Debug)]
+pub struct Spec {
+    pub name: &'static str,
+    pub usage: &'static str, // arguments after the name
+    pub help: &'static str,  // empty for commands not shown in the help
+    pub tui_only: bool,
+    build: fn(&mut Args) -> Command, // from the arguments matching the usage
+}
+
+/// Arguments split as declared by the usage of a command
+struct Args(std::vec::IntoIter<Option<String>>);
+
+impl Args {
+    fn optional(&mut self) -> Option<String> {
+        self.0.next().flatten()
+    }
+
+    fn required(&mut self) -> String {
+        self.optional()
+            .expect("required arguments are checked by the usage")
+    }
+}
+
+pub const COMMANDS: &[Spec] = &[
+    Spec {
+        name: "help",
+        usage: "[command]",
+        help: "Show the commands, or the usage of one",
+        tui_only: false,
+        build: |args| Command::Help(args.optional()),
+    },
+    Spec {
+        name: "exit",
+        usage: "",
+        help: "Leave the chat",
+        tui_only: false,
+        build: |_| Command::Exit,
+    },
+    Spec {
+        name: "nick",
+        usage: "<name>",
+        help: "Change your name",
+        tui_only: false,
+        build: |args| Command::Nick(args.required()),
+    },
+    Spec {
+        name: "join",
+        usage: "<room>",
+        help: "Switch to a room",
+        tui_only: false,
+        build: |args| Command::Join(args.required().trim_start_matches('#').to_owned()),
+    },
+    Spec {
+        name: "me",
+        usage: "<action...>",
+        help: "Tell what you are doing, e.g. `:me waves`",
+        tui_only: false,
+        build: |args| Command::Me(args.required()),
+    },
+    Spec {
+        name: "msg",
+        usage: "<user> <text...>",
+        help: "Send a direct message to a user",
+        tui_only: false,
+        build: |args| {
+            Command::Msg(
+                args.required().trim_start_matches('@').to_owned(),
+                args.required(),
+            )
+        },
+    },
+    Spec {
+        name: "clear",
+        usage: "",
+        help: "Clear the messages of the pane",
+        tui_only: true,
+        build: |_| Command::Clear,
+    },
+    Spec {
+        name: "raw",
+        usage: "",
+        help: "Toggle showing the markup of messages as it is",
+        tui_only: true,
+        build: |_| Command::Raw,
+    },
+    Spec {
+        name: "theme",
+        usage: "[name]",
+        help: "Switch to a theme, or list them",
+        tui_only: true,
+        build: |args| Command::Theme(args.optional()),
+    },
+    Spec {
+        name: "fuck",
+        usage: "",
+        help: "",
+        tui_only: true,
+        build: |_| Command::Fuck,
+    },
+];
+
+/// A parsed client command
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command {
+    Help(Option<String>),
+    Exit,
+    Nick(String),
+    Join(String),
+    Me(String),
+    Msg(User, String),
+    Clear,
+    Raw,
+    Theme(Option<String>),
+    Fuck,
+}
+
+impl Spec {
+    fn find(name: &str) -> Option<&'static Spec> {
+        let name = name.trim_start_matches(':').to_lowercase();
+        COMMANDS.iter().find(|spec| spec.name == name)
+    }
+
+    fn is_available(&self, tui: bool) -> bool {
+        tui || !self.tui_only
+    }
+
+    /// e.g. `:msg <user> <text...>`
+    pub fn synopsis(&self) -> String {
+        if self.usage.is_empty() {
+            format!(":{}", self.name)
+        } else {
+            format!(":{} {}", self.name, self.usage)
+        }
+    }
+
+    /// Split `args` as declared by the usage, `None` if they don't match
+    fn split_args(&self, args: &str) -> Option<Args> {
+        let params: Vec<_> = self.usage.split_whitespace().collect();
+        let mut rest = args.trim();
+        let mut values = Vec::new();
+        for (i, param) in params.iter().enumerate() {
+            let value = if param.ends_with("...>") || param.ends_with("...]") {
+                std::mem::take(&mut rest)
+            } else {
+                let (value, tail) = rest.split_once(' ').unwrap_or((rest, ""));
+                rest = tail.trim_start();
+                value
+            };
+            match value {
+                "" if param.starts_with('<') => return None,
+                "" => values.push(None),
+                value => values.push(Some(value.to_owned())),
+            }
+            if i == params.len() - 1 && !rest.is_empty() {
+                return None;
+            }
+        }
+        if params.is_empty() && !rest.is_empty() {
+            return None;
+        }
+        Some(Args(values.into_iter()))
+    }
+}
+
+/// Parse a line typed in an app, `None` if it's not a command, such as `:)` or any text whose
+/// first word isn't the name of a command after the colon.
+/// Commands only for the terminal UI are rejected unless `tui`.
+pub fn parse(line: &str, tui: bool) -> Option<Result<Command, String>> {
+    let line = line.trim_end().strip_prefix(':')?;
+    let (name, args) = line.split_once(' ').unwrap_or((line, ""));
+    let spec = Spec::find(name)?;
+    if !spec.is_available(tui) {
+        return Some(Err(format!(
+            "`:{}` is only available in the terminal UI",
+            spec.name
+        )));
+    }
+    Some(match spec.split_args(args) {
+        Some(mut args) => Ok((spec.build)(&mut args)),
+        None => Err(format!("Usage: {}", spec.synopsis())),
+    })
+}
+
+/// Lines of the help of all the commands available, or the usage of one
+pub fn help(command: Option<&str>, tui: bool) -> Vec<String> {
+    match command.map(|name| Spec::find(name).filter(|spec| spec.is_available(tui))) {
+        Some(Some(spec)) => vec![format!("Usage: {}", spec.synopsis()), spec.help.to_owned()],
+        Some(None) => vec![format!("Unknown command `{}`", command.unwrap())],
+        None => {
+            let specs: Vec<_> = COMMANDS
+                .iter()
+                .filter(|spec| spec.is_available(tui) && !spec.help.is_empty())
+                .collect();
+            let width = specs.iter().map(|s| s.synopsis().len()).max().unwrap_or(0);
+            let mut lines = vec!["Commands:".to_owned()];
+            lines.extend(
+                specs.iter().map(|spec| {
+                    format!("  {:width$}  {}", spec.synopsis(), spec.help, width = width)
+                }),
+            );
+            lines
+        }
+    }
+}
+
+/// Names of the commands to complete, those hidden from the help excepted
+pub fn names(tui: bool) -> impl Iterator<Item = &'static str> {
+    COMMANDS
+        .iter()
+        .filter(move |spec| spec.is_available(tui) && !spec.help.is_empty())
+        .map(|spec| spec.name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ok(line: &str) -> Command {
+        parse(line, true).unwrap().unwrap()
+    }
+
+    fn err(line: &str, tui: bool) -> String {
+        parse(line, tui).unwrap().unwrap_err()
+    }
+
+    #[test]
+    fn commands() {
+        assert_eq!(ok(":exit"), Command::Exit);
+        assert_eq!(ok(":EXIT  "), Command::Exit);
+        assert_eq!(ok(":help"), Command::Help(None));
+        assert_eq!(ok(":help nick"), Command::Help(Some("nick".to_owned())));
+        assert_eq!(ok(":nick bob"), Command::Nick("bob".to_owned()));
+        assert_eq!(ok(":join #rust"), Command::Join("rust".to_owned()));
+        assert_eq!(
+            ok(":me waves  at you"),
+            Command::Me("waves  at you".to_owned())
+        );
+        assert_eq!(
+            ok(":msg @bob hi  there"),
+            Command::Msg("bob".to_owned(), "hi  there".to_owned())
+        );
+        assert_eq!(ok(":theme"), Command::Theme(None));
+        assert_eq!(ok(":theme light"), Command::Theme(Some("light".to_owned())));
+    }
+
+    #[test]
+    fn every_command_parses() {
+        for spec in COMMANDS {
+            let args: Vec<_> = (spec.usage.split_whitespace())
+                .filter(|param| param.starts_with('<'))
+                .map(|_| "x")
+                .collect();
+            let line = format!(":{} {}", spec.name, args.join(" "));
+            assert!(matches!(parse(&line, true), Some(Ok(_))), "{}", line);
+        }
+    }
+
+    #[test]
+    fn usage_errors() {
+        assert_eq!(err(":nick", true), "Usage: :nick <name>");
+        assert_eq!(err(":nick bob alice", true), "Usage: :nick <name>");
+        assert_eq!(err(":exit now", true), "Usage: :exit");
+        assert_eq!(err(":msg bob", true), "Usage: :msg <user> <text...>");
+        assert_eq!(
+            err(":clear", false),
+            "`:clear` is only available in the terminal UI"
+        );
+    }
+
+    #[test]
+    fn text_is_no_command() {
+        assert_eq!(parse("hello", true), None);
+        assert_eq!(parse(" :exit", true), None);
+        assert_eq!(parse(":)", true), None);
+        assert_eq!(parse(":-D", false), None);
+        assert_eq!(parse(":nope at all", true), None);
+    }
+}
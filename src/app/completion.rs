@@ -0,0 +1,144 @@
+//! Tab completion of the word before the cursor
+
+use super::command;
+use super::input_line::InputLine;
+
+/// Candidates of an ongoing completion, cycled by repeated Tabs
+#[derive(Debug)]
+pub struct Completion {
+    start: usize, // byte offset of the completed word in the input
+    pub candidates: Vec<String>,
+    pub selected: usize,
+}
+
+impl Completion {
+    /// Start completing the word before the cursor, the first candidate being applied
+    ///
+    /// - `@us` completes to a user name
+    /// - `:cl` at the beginning of the input completes to a command
+    /// - `#lo` completes to a room name
+    pub fn start(input: &mut InputLine, users: &[String], rooms: &[String]) -> Option<Self> {
+        let (start, word) = input.word_before_cursor();
+        let (sigil, prefix) = match word.chars().next()? {
+            sigil @ '@' | sigil @ '#' => (sigil, &word[1..]),
+            ':' if start == 0 => (':', &word[1..]),
+            _ => return None,
+        };
+        let names: Vec<&str> = match sigil {
+            '@' => users.iter().map(String::as_str).collect(),
+            '#' => rooms.iter().map(String::as_str).collect(),
+            _ => command::names(true).collect(),
+        };
+
+        let prefix = prefix.to_lowercase();
+        let mut candidates: Vec<String> = names
+            .into_iter()
+            .filter(|name| name.to_lowercase().starts_with(&prefix))
+            .map(|name| format!("{}{}", sigil, name))
+            .collect();
+        candidates.sort_by_key(|c| c.to_lowercase());
+        candidates.dedup();
+        if candidates.is_empty() {
+            return None;
+        }
+
+        let completion = Self {
+            start,
+            candidates,
+            selected: 0,
+        };
+        completion.apply(input);
+        Some(completion)
+    }
+
+    /// Whether there's a choice to show, a single candidate is simply applied
+    pub fn is_ambiguous(&self) -> bool {
+        self.candidates.len() > 1
+    }
+
+    /// Apply the next candidate, or the previous one if `backward`
+    pub fn cycle(&mut self, input: &mut InputLine, backward: bool) {
+        let n = self.candidates.len();
+        self.selected = if backward {
+            (self.selected + n - 1) % n
+        } else {
+            (self.selected + 1) % n
+        };
+        self.apply(input);
+    }
+
+    fn apply(&self, input: &mut InputLine) {
+        input.replace_before_cursor(self.start, &self.candidates[self.selected]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn line(text: &str) -> InputLine {
+        let mut line = InputLine::default();
+        line.set(text.to_owned());
+        line
+    }
+
+    #[test]
+    fn candidates_by_sigil() {
+        let users = names(&["bob", "Alice", "albert"]);
+        let rooms = names(&["lobby", "rust"]);
+
+        let mut input = line("hi @al");
+        let completion = Completion::start(&mut input, &users, &rooms).unwrap();
+        assert_eq!(completion.candidates, ["@albert", "@Alice"]);
+        assert_eq!(input.text(), "hi @albert");
+
+        let mut input = line("#r");
+        Completion::start(&mut input, &users, &rooms).unwrap();
+        assert_eq!(input.text(), "#rust");
+
+        let mut input = line(":jo");
+        let completion = Completion::start(&mut input, &users, &rooms).unwrap();
+        assert!(!completion.is_ambiguous());
+        assert_eq!(input.text(), ":join");
+
+        // commands only at the beginning, and nothing without a sigil or candidate
+        assert!(Completion::start(&mut line("x :jo"), &users, &rooms).is_none());
+        assert!(Completion::start(&mut line("bo"), &users, &rooms).is_none());
+        assert!(Completion::start(&mut line("@zed"), &users, &rooms).is_none());
+    }
+
+    #[test]
+    fn cycle_wraps_around() {
+        let users = names(&["ann", "amy", "al"]);
+        let mut input = line("@a");
+        let mut completion = Completion::start(&mut input, &users, &[]).unwrap();
+        assert!(completion.is_ambiguous());
+        assert_eq!(input.text(), "@al");
+
+        let mut texts = Vec::new();
+        for _ in 0..3 {
+            completion.cycle(&mut input, false);
+            texts.push(input.text().to_owned());
+        }
+        assert_eq!(texts, ["@amy", "@ann", "@al"]);
+
+        completion.cycle(&mut input, true);
+        assert_eq!(input.text(), "@ann");
+        assert_eq!(completion.selected, 2);
+    }
+
+    #[test]
+    fn cycle_keeps_the_text_before() {
+        let users = names(&["ann", "amy"]);
+        let mut input = line("hey @a");
+        let mut completion = Completion::start(&mut input, &users, &[]).unwrap();
+        completion.cycle(&mut input, false);
+        assert_eq!(input.text(), "hey @ann");
+        completion.cycle(&mut input, false);
+        assert_eq!(input.text(), "hey @amy");
+    }
+}
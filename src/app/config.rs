@@ -0,0 +1,83 @@
+//! Options of the apps given on the command line and in the config file
+
+use std::{str::FromStr, time::Duration};
+
+use super::theme::Themes;
+use super::Palette;
+
+/// How to notify the user of a mention
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Notify {
+    None,
+    #[default]
+    Bell,
+    /// A desktop notification through the OSC 9 escape sequence, supported by many terminals
+    Osc,
+}
+
+impl FromStr for Notify {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(Notify::None),
+            "bell" => Ok(Notify::Bell),
+            "osc" => Ok(Notify::Osc),
+            _ => Err(format!(
+                "unknown notification `{}`, expect none, bell or osc",
+                s
+            )),
+        }
+    }
+}
+
+/// How `TuiApp` tells users joining, leaving or changing their names
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Membership {
+    #[default]
+    Show,
+    /// Consecutive changes on one line
+    Collapse,
+    Hide,
+}
+
+impl FromStr for Membership {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "show" => Ok(Membership::Show),
+            "collapse" => Ok(Membership::Collapse),
+            "hide" => Ok(Membership::Hide),
+            _ => Err(format!(
+                "unknown membership mode `{}`, expect show, collapse or hide",
+                s
+            )),
+        }
+    }
+}
+
+/// Options of the headless mode, which sends messages for a script then exits
+#[derive(Clone, Debug, Default)]
+pub struct Headless {
+    /// Sent instead of the lines of stdin
+    pub message: Option<String>,
+    /// Number of messages of other users to wait for after sending
+    pub replies: usize,
+    /// Fail if not done after this long, from connecting to the last reply
+    pub timeout: Option<Duration>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    /// Words that count as a mention besides `@username`
+    pub keywords: Vec<String>,
+    pub notify: Notify,
+    pub membership: Membership,
+    pub palette: Palette,
+    /// Set by the `NO_COLOR` environment variable, see https://no-color.org
+    pub no_color: bool,
+    pub themes: Themes,
+    /// `Some` to run the headless app instead of an interactive one
+    pub headless: Option<Headless>,
+}
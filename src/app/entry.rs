@@ -0,0 +1,307 @@
+//! Messages shown by `TuiApp`, styled only when drawn so that the theme can change
+
+use std::{fmt, ops::Range};
+
+use tui::style::{Modifier, Style};
+use unicode_width::UnicodeWidthStr;
+
+use super::theme::Theme;
+use super::wrap::StyledString;
+use super::Palette;
+use crate::message::{parse_markup, Markup, User};
+
+/// How a message of a user is told, after the kinds of `Message`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Voice {
+    Text,
+    Action,
+    Notice,
+}
+
+/// A user joining, leaving or changing their name
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MemberChange {
+    Joined(User),
+    Left(User),
+    Renamed(User, User),
+}
+
+impl fmt::Display for MemberChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MemberChange::Joined(user) => write!(f, "{} joined", user),
+            MemberChange::Left(user) => write!(f, "{} left", user),
+            MemberChange::Renamed(old, new) => write!(f, "{} is now known as {}", old, new),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum EntryKind {
+    /// A message of a user, with the byte ranges of the mentions of us in the text, and its id
+    /// in the history of the server if it's posted in a room
+    User {
+        id: Option<usize>,
+        user: User,
+        voice: Voice,
+        mentions: Vec<Range<usize>>,
+    },
+    Server,
+    /// Consecutive membership changes, told on one line when collapsed
+    Membership(Vec<MemberChange>),
+    Error,
+}
+
+/// A message to show, lines after the first one are indented to align with its text
+#[derive(Clone, Debug)]
+pub struct Entry {
+    pub kind: EntryKind,
+    prefix: String,
+    text: String,
+}
+
+impl Entry {
+    /// e.g. `[bob, 12:00:00] hi`, `[12:00:00] * bob waves` or `-bob, 12:00:00- hi`
+    pub fn user(
+        id: Option<usize>,
+        user: User,
+        voice: Voice,
+        text: String,
+        mentions: Vec<Range<usize>>,
+    ) -> Self {
+        let time = chrono::Local::now().format("%H:%M:%S");
+        Self {
+            prefix: match voice {
+                Voice::Text => format!("[{}, {}] ", user, time),
+                Voice::Action => format!("[{}] * {} ", time, user),
+                Voice::Notice => format!("-{}, {}- ", user, time),
+            },
+            kind: EntryKind::User {
+                id,
+                user,
+                voice,
+                mentions,
+            },
+            text,
+        }
+    }
+
+    pub fn server(text: String) -> Self {
+        Self {
+            kind: EntryKind::Server,
+            prefix: "=> ".to_owned(),
+            text,
+        }
+    }
+
+    pub fn membership(change: MemberChange) -> Self {
+        Self {
+            prefix: "=> ".to_owned(),
+            text: change.to_string(),
+            kind: EntryKind::Membership(vec![change]),
+        }
+    }
+
+    /// Collapse a membership change into this entry, return whether it's a membership one
+    pub fn add_change(&mut self, change: MemberChange) -> bool {
+        match &mut self.kind {
+            EntryKind::Membership(changes) => {
+                changes.push(change);
+                self.text = describe_changes(changes);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn error(text: String) -> Self {
+        Self {
+            kind: EntryKind::Error,
+            prefix: "=> ".to_owned(),
+            text,
+        }
+    }
+
+    pub fn indent(&self) -> usize {
+        self.prefix.width()
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// The author, if it's a message of a user
+    pub fn author(&self) -> Option<&User> {
+        match &self.kind {
+            EntryKind::User { user, .. } => Some(user),
+            _ => None,
+        }
+    }
+
+    /// The id in the history of the server, if it's a message posted in a room
+    pub fn id(&self) -> Option<usize> {
+        match &self.kind {
+            EntryKind::User { id, .. } => *id,
+            _ => None,
+        }
+    }
+
+    pub fn is_mention(&self) -> bool {
+        matches!(&self.kind, EntryKind::User { mentions, .. } if !mentions.is_empty())
+    }
+
+    /// Style the prefix and the text, rendering the markup of user messages unless `raw`
+    pub fn spans(&self, theme: &Theme, palette: Palette, raw: bool) -> Vec<StyledString> {
+        let (user, voice, mentions) = match &self.kind {
+            EntryKind::User {
+                user,
+                voice,
+                mentions,
+                ..
+            } => (user, *voice, mentions),
+            EntryKind::Server => {
+                return vec![(self.prefix.clone() + &self.text, theme.server())];
+            }
+            EntryKind::Membership(_) => {
+                return vec![(self.prefix.clone() + &self.text, theme.notice())];
+            }
+            EntryKind::Error => {
+                return vec![(self.prefix.clone() + &self.text, theme.error())];
+            }
+        };
+
+        let prefix_style = if mentions.is_empty() {
+            Style::default().fg(palette.user_color(user))
+        } else {
+            theme.mention()
+        };
+        let mut spans = vec![(self.prefix.clone(), prefix_style)];
+        let text_style = match voice {
+            Voice::Text => theme.text(),
+            Voice::Action => theme.text().add_modifier(Modifier::ITALIC),
+            Voice::Notice => theme.notice(),
+        };
+
+        // split the text at the mentions, then render the markup of every piece
+        let mut pieces = Vec::new();
+        let mut last = 0;
+        for range in mentions {
+            pieces.push((&self.text[last..range.start], text_style));
+            pieces.push((&self.text[range.clone()], theme.mention()));
+            last = range.end;
+        }
+        pieces.push((&self.text[last..], text_style));
+
+        for (text, style) in pieces.into_iter().filter(|(text, _)| !text.is_empty()) {
+            if raw {
+                spans.push((text.to_owned(), style));
+            } else {
+                spans.extend(
+                    parse_markup(text)
+                        .into_iter()
+                        .map(|segment| (segment.text, markup_style(theme, style, segment.markup))),
+                );
+            }
+        }
+        spans
+    }
+}
+
+/// e.g. `bob and carol joined, dave left, eve is now known as eva`, merging only consecutive
+/// joins or leaves so that the order of the changes is kept
+fn describe_changes(changes: &[MemberChange]) -> String {
+    fn user_and_verb(change: &MemberChange) -> Option<(&str, &str)> {
+        match change {
+            MemberChange::Joined(user) => Some((user, "joined")),
+            MemberChange::Left(user) => Some((user, "left")),
+            MemberChange::Renamed(..) => None,
+        }
+    }
+    let same_verb = |a: &MemberChange, b: &MemberChange| match (user_and_verb(a), user_and_verb(b))
+    {
+        (Some((_, a)), Some((_, b))) => a == b,
+        _ => false,
+    };
+
+    let parts: Vec<_> = (changes.chunk_by(same_verb))
+        .map(|group| match group {
+            [change] => change.to_string(),
+            _ => {
+                let (users, verbs): (Vec<_>, Vec<_>) =
+                    group.iter().filter_map(user_and_verb).unzip();
+                let (last, rest) = users.split_last().unwrap();
+                format!("{} and {} {}", rest.join(", "), last, verbs[0])
+            }
+        })
+        .collect();
+    parts.join(", ")
+}
+
+fn markup_style(theme: &Theme, style: Style, markup: Markup) -> Style {
+    let mut style = style;
+    if markup.bold {
+        style = style.add_modifier(Modifier::BOLD);
+    }
+    if markup.italic {
+        style = style.add_modifier(Modifier::ITALIC);
+    }
+    if markup.code {
+        style = style.patch(theme.code());
+    }
+    if markup.url {
+        style = style.patch(theme.url());
+    }
+    style
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn joined(user: &str) -> MemberChange {
+        MemberChange::Joined(user.to_owned())
+    }
+
+    fn left(user: &str) -> MemberChange {
+        MemberChange::Left(user.to_owned())
+    }
+
+    fn renamed(old: &str, new: &str) -> MemberChange {
+        MemberChange::Renamed(old.to_owned(), new.to_owned())
+    }
+
+    #[test]
+    fn consecutive_changes_are_merged() {
+        let changes = [joined("bob"), joined("carol"), joined("dave"), left("eve")];
+        assert_eq!(
+            describe_changes(&changes),
+            "bob, carol and dave joined, eve left"
+        );
+        assert_eq!(describe_changes(&[left("bob")]), "bob left");
+        assert_eq!(describe_changes(&[]), "");
+    }
+
+    #[test]
+    fn order_is_kept() {
+        let changes = [left("bob"), joined("bob")];
+        assert_eq!(describe_changes(&changes), "bob left, bob joined");
+        let changes = [joined("bob"), left("carol"), joined("dave")];
+        assert_eq!(
+            describe_changes(&changes),
+            "bob joined, carol left, dave joined"
+        );
+        let changes = [renamed("bob", "rob"), joined("bob"), joined("eve")];
+        assert_eq!(
+            describe_changes(&changes),
+            "bob is now known as rob, bob and eve joined"
+        );
+    }
+
+    #[test]
+    fn collapsed_entry() {
+        let mut entry = Entry::membership(left("bob"));
+        assert!(entry.add_change(joined("bob")));
+        assert_eq!(entry.text(), "bob left, bob joined");
+        assert!(!Entry::server(String::new()).add_change(joined("bob")));
+    }
+}
@@ -0,0 +1,118 @@
+//! An app for scripts, e.g. `echo hi | chat client --headless --wait 1`
+//!
+//! Once the server accepts our name, it sends the lines of stdin or the given message, prints
+//! the messages of other users, and exits when all is sent and enough replies have come.
+//! A failure is returned to the client as an error, telling the exit code.
+
+use std::io::BufRead;
+
+use tokio::sync::mpsc;
+
+use super::basic_app::print_message;
+use super::Config;
+use crate::{client::ClientInput, error::*, message::Message, protocol::ServerCommand};
+
+type Tx<T> = mpsc::UnboundedSender<T>;
+type Rx<T> = mpsc::UnboundedReceiver<T>;
+
+/// An app without interaction, run by `Config::headless`
+pub struct HeadlessApp {}
+
+impl super::App for HeadlessApp {
+    fn start(
+        input_tx: Tx<ClientInput>,
+        msg_rx: Rx<ServerCommand>,
+        name: &str,
+        config: Config,
+    ) -> Result<()> {
+        let headless = config.headless.unwrap_or_default();
+        let output = Output {
+            markup: termion::is_tty(&std::io::stdout()),
+            colors: !config.no_color,
+        };
+
+        let (line_tx, line_rx) = mpsc::unbounded_channel();
+        match headless.message.clone() {
+            Some(message) => {
+                let _ = line_tx.send(message);
+            }
+            // reading stdin blocks, so run it on a blocking thread instead of starving the runtime
+            None => {
+                tokio::task::spawn_blocking(move || {
+                    for line in std::io::stdin().lock().lines().map_while(|line| line.ok()) {
+                        if line_tx.send(line).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        }
+
+        let name = name.to_owned();
+        let replies = headless.replies;
+        // the timeout is up to the client, which also covers connecting
+        tokio::spawn(async move {
+            let result = run(&input_tx, msg_rx, line_rx, &name, replies, output).await;
+            let _ = input_tx.send(match result {
+                Ok(()) => ClientInput::Exit,
+                Err(e) => ClientInput::Fail(e),
+            });
+        });
+
+        Ok(())
+    }
+}
+
+/// How to print the messages, as `BasicApp` does
+#[derive(Clone, Copy)]
+struct Output {
+    markup: bool,
+    colors: bool,
+}
+
+/// Wait for the server to accept `name`, send the lines, then wait for `replies` messages
+async fn run(
+    input_tx: &Tx<ClientInput>,
+    mut msg_rx: Rx<ServerCommand>,
+    mut line_rx: Rx<String>,
+    name: &str,
+    replies: usize,
+    output: Output,
+) -> Result<()> {
+    // the server tells its name once it accepts ours, and an error otherwise
+    loop {
+        match msg_rx.recv().await.ok_or(Error::Disconnected)? {
+            ServerCommand::ServerName(_) => break,
+            ServerCommand::Error(e) => return Err(Error::NameRejected(e)),
+            _ => {}
+        }
+    }
+
+    let mut sending = true;
+    let mut received = 0;
+    while sending || received < replies {
+        tokio::select! {
+            line = line_rx.recv(), if sending => match line {
+                Some(line) if !line.trim().is_empty() => {
+                    let _ = input_tx.send(ClientInput::Message(Message::Text(line)));
+                }
+                Some(_) => {}
+                None => sending = false,
+            },
+            command = msg_rx.recv() => match command.ok_or(Error::Disconnected)? {
+                ServerCommand::UserMessage(user, message, _) if user != name => {
+                    print_message(&user, &message, output.markup, output.colors);
+                    received += 1;
+                }
+                ServerCommand::DirectMessage(from, to, message) if from != name => {
+                    let who = format!("{} -> {}", from, to);
+                    print_message(&who, &message, output.markup, output.colors);
+                    received += 1;
+                }
+                ServerCommand::Error(e) => eprintln!("Error: {}", e),
+                _ => {}
+            },
+        }
+    }
+    Ok(())
+}
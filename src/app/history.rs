@@ -0,0 +1,286 @@
+//! History of sent lines, persisted to the user's data dir
+
+use std::{
+    collections::VecDeque,
+    fs,
+    io::{self, Write},
+    path::PathBuf,
+};
+
+/// How many lines are kept
+const MAX_HISTORY: usize = 1000;
+/// How many lines the file can grow to by appending, before it is rewritten with the kept ones
+const MAX_FILE_LINES: usize = 2 * MAX_HISTORY;
+
+/// Sent lines, oldest first, with a position for browsing them
+#[derive(Default, Debug)]
+pub struct History {
+    entries: VecDeque<String>,
+    pos: Option<usize>, // index of the recalled entry, `None` if not browsing
+    draft: String,      // the input before browsing, restored after the newest entry
+    path: Option<PathBuf>,
+    file_lines: usize, // lines in the file, kept ones or not
+}
+
+impl History {
+    /// Load the history file, starting empty if there's none
+    pub fn load() -> Self {
+        let path = dirs::data_dir().map(|dir| dir.join("chat").join("history"));
+        Self::load_from(path)
+    }
+
+    fn load_from(path: Option<PathBuf>) -> Self {
+        let entries: VecDeque<String> = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|content| content.lines().map(unescape).collect())
+            .unwrap_or_default();
+
+        let mut history = Self {
+            file_lines: entries.len(),
+            entries,
+            path,
+            ..Self::default()
+        };
+        history.truncate();
+        history
+    }
+
+    fn truncate(&mut self) {
+        while self.entries.len() > MAX_HISTORY {
+            self.entries.pop_front();
+        }
+    }
+
+    /// Append the newest entry to the file, or rewrite it with the kept entries once it's too long
+    fn save(&mut self) -> io::Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        if self.file_lines >= MAX_FILE_LINES {
+            let mut file = io::BufWriter::new(fs::File::create(path)?);
+            for entry in &self.entries {
+                writeln!(file, "{}", escape(entry))?;
+            }
+            file.flush()?;
+            self.file_lines = self.entries.len();
+        } else if let Some(entry) = self.entries.back() {
+            let mut file = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?;
+            writeln!(file, "{}", escape(entry))?;
+            self.file_lines += 1;
+        }
+        Ok(())
+    }
+
+    /// Record a sent line and stop browsing
+    pub fn push(&mut self, line: String) {
+        self.pos = None;
+        if line.trim().is_empty() || self.entries.back() == Some(&line) {
+            return;
+        }
+        self.entries.push_back(line);
+        self.truncate();
+        // losing the history is not worth disturbing the chat
+        let _ = self.save();
+    }
+
+    /// Recall the entry before the current one, `input` is kept as the draft if not browsing
+    pub fn prev(&mut self, input: &str) -> Option<&str> {
+        let pos = match self.pos {
+            Some(0) => return None,
+            Some(pos) => pos - 1,
+            None if self.entries.is_empty() => return None,
+            None => {
+                self.draft = input.to_owned();
+                self.entries.len() - 1
+            }
+        };
+        self.pos = Some(pos);
+        Some(&self.entries[pos])
+    }
+
+    /// Recall the entry after the current one, or the draft after the newest one
+    pub fn next(&mut self) -> Option<&str> {
+        let pos = self.pos?;
+        if pos + 1 < self.entries.len() {
+            self.pos = Some(pos + 1);
+            Some(&self.entries[pos + 1])
+        } else {
+            self.pos = None;
+            Some(&self.draft)
+        }
+    }
+
+    /// Find the newest entry containing `query` before index `before`
+    pub fn search(&self, query: &str, before: usize) -> Option<(usize, &str)> {
+        self.entries
+            .iter()
+            .enumerate()
+            .take(before)
+            .rev()
+            .find(|(_, entry)| entry.contains(query))
+            .map(|(i, entry)| (i, entry.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+/// State of a reverse incremental search
+#[derive(Default, Debug)]
+pub struct ReverseSearch {
+    pub query: String,
+    pub found: Option<usize>, // index of the matched entry
+    pub failed: bool,         // whether the last search found nothing
+    pub original: String,     // the input before searching, restored on cancel
+}
+
+impl ReverseSearch {
+    pub fn new(original: String) -> Self {
+        Self {
+            original,
+            ..Self::default()
+        }
+    }
+
+    /// Search again after the query changed, the current match being kept if still matching
+    pub fn update<'a>(&mut self, history: &'a History) -> Option<&'a str> {
+        let before = self.found.map_or(history.len(), |i| i + 1);
+        self.search(history, before)
+    }
+
+    /// Search for an older match of the query
+    pub fn next<'a>(&mut self, history: &'a History) -> Option<&'a str> {
+        let before = self.found.unwrap_or_else(|| history.len());
+        self.search(history, before)
+    }
+
+    fn search<'a>(&mut self, history: &'a History, before: usize) -> Option<&'a str> {
+        match history.search(&self.query, before) {
+            Some((i, entry)) => {
+                self.found = Some(i);
+                self.failed = false;
+                Some(entry)
+            }
+            None => {
+                self.failed = true;
+                None
+            }
+        }
+    }
+}
+
+/// Keep one entry per line in the file
+fn escape(line: &str) -> String {
+    line.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn unescape(line: &str) -> String {
+    let mut unescaped = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                unescaped.push('\n');
+                chars.next();
+            }
+            ('\\', Some('\\')) => {
+                unescaped.push('\\');
+                chars.next();
+            }
+            (c, _) => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(lines: &[&str]) -> History {
+        let mut history = History::default();
+        for line in lines {
+            history.push((*line).to_owned());
+        }
+        history
+    }
+
+    #[test]
+    fn dedup_and_blank_lines() {
+        let history = history(&["a", "a", " ", "", "b", "a"]);
+        assert_eq!(history.entries, ["a", "b", "a"]);
+    }
+
+    #[test]
+    fn cap() {
+        let lines: Vec<String> = (0..MAX_HISTORY + 5).map(|i| i.to_string()).collect();
+        let history = history(&lines.iter().map(String::as_str).collect::<Vec<_>>());
+        assert_eq!(history.len(), MAX_HISTORY);
+        assert_eq!(history.entries.front().map(String::as_str), Some("5"));
+    }
+
+    #[test]
+    fn navigation() {
+        let mut history = history(&["a", "b"]);
+        assert_eq!(history.next(), None);
+        assert_eq!(history.prev("draft"), Some("b"));
+        assert_eq!(history.prev("ignored"), Some("a"));
+        assert_eq!(history.prev("ignored"), None);
+        assert_eq!(history.next(), Some("b"));
+        assert_eq!(history.next(), Some("draft"));
+        assert_eq!(history.next(), None);
+
+        // pushing stops browsing
+        history.prev("");
+        history.push("c".to_owned());
+        assert_eq!(history.prev(""), Some("c"));
+    }
+
+    #[test]
+    fn reverse_search() {
+        let history = history(&["foo 1", "bar", "foo 2"]);
+        let mut search = ReverseSearch::new(String::new());
+        search.query = "foo".to_owned();
+        assert_eq!(search.update(&history), Some("foo 2"));
+        assert_eq!(search.next(&history), Some("foo 1"));
+        assert_eq!(search.next(&history), None);
+        assert!(search.failed);
+    }
+
+    #[test]
+    fn file_is_appended_then_compacted() {
+        let path = std::env::temp_dir().join(format!("chat-history-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let count_lines = || fs::read_to_string(&path).unwrap().lines().count();
+
+        let mut history = History::load_from(Some(path.clone()));
+        history.push("multi\nline\\".to_owned());
+        history.push("multi\nline\\".to_owned());
+        assert_eq!(count_lines(), 1);
+        assert_eq!(
+            History::load_from(Some(path.clone())).entries,
+            history.entries
+        );
+
+        for i in 1..MAX_FILE_LINES {
+            history.push(i.to_string());
+        }
+        assert_eq!(count_lines(), MAX_FILE_LINES);
+        history.push("last".to_owned());
+        assert_eq!(count_lines(), MAX_HISTORY);
+
+        let loaded = History::load_from(Some(path.clone()));
+        assert_eq!(loaded.entries, history.entries);
+        assert_eq!(loaded.entries.back().map(String::as_str), Some("last"));
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,311 @@
+//! A text editor with emacs-like key bindings, for one or more lines
+
+use termion::event::Key;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// The edited text with a cursor, and the last killed text to yank back
+#[derive(Default, Debug)]
+pub struct InputLine {
+    text: String,
+    cursor: usize, // byte offset in `text`, always on a char boundary
+    killed: String,
+}
+
+impl InputLine {
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    /// Replace the text, moving the cursor to the end
+    pub fn set(&mut self, text: String) {
+        self.cursor = text.len();
+        self.text = text;
+    }
+
+    /// Take the text out, leaving the line empty
+    pub fn take(&mut self) -> String {
+        self.cursor = 0;
+        std::mem::take(&mut self.text)
+    }
+
+    /// Apply an editing key, return whether it was handled
+    pub fn handle_key(&mut self, key: Key) -> bool {
+        match key {
+            Key::Char('\n') => return false,
+            Key::Char(c) => self.insert(c),
+            Key::Backspace | Key::Ctrl('h') => {
+                let start = self.prev_char();
+                self.text.replace_range(start..self.cursor, "");
+                self.cursor = start;
+            }
+            Key::Delete | Key::Ctrl('d') => {
+                let end = self.next_char();
+                self.text.replace_range(self.cursor..end, "");
+            }
+            Key::Left | Key::Ctrl('b') => self.cursor = self.prev_char(),
+            Key::Right | Key::Ctrl('f') => self.cursor = self.next_char(),
+            Key::Home | Key::Ctrl('a') => self.cursor = self.line_start(self.cursor),
+            Key::End | Key::Ctrl('e') => self.cursor = self.line_end(self.cursor),
+            Key::Up if self.is_multiline() => self.move_line(true),
+            Key::Down if self.is_multiline() => self.move_line(false),
+            Key::Alt('b') => self.cursor = self.prev_word(),
+            Key::Alt('f') => self.cursor = self.next_word(),
+            Key::Ctrl('w') => self.kill(self.prev_word(), self.cursor),
+            Key::Alt('d') => self.kill(self.cursor, self.next_word()),
+            Key::Ctrl('u') => self.kill(0, self.cursor),
+            Key::Ctrl('k') => self.kill(self.cursor, self.text.len()),
+            Key::Ctrl('y') => {
+                let killed = self.killed.clone();
+                self.text.insert_str(self.cursor, &killed);
+                self.cursor += killed.len();
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// The word being typed before the cursor, with its byte offset
+    pub fn word_before_cursor(&self) -> (usize, &str) {
+        let start = self.text[..self.cursor]
+            .rfind(char::is_whitespace)
+            .map_or(0, |i| i + self.text[i..].chars().next().unwrap().len_utf8());
+        (start, &self.text[start..self.cursor])
+    }
+
+    /// Replace the text from `start` to the cursor, moving the cursor after the replacement
+    pub fn replace_before_cursor(&mut self, start: usize, replacement: &str) {
+        self.text.replace_range(start..self.cursor, replacement);
+        self.cursor = start + replacement.len();
+    }
+
+    pub fn is_multiline(&self) -> bool {
+        self.text.contains('\n')
+    }
+
+    pub fn insert(&mut self, c: char) {
+        self.text.insert(self.cursor, c);
+        self.cursor += c.len_utf8();
+    }
+
+    /// Remove the text between `start` and `end`, keeping it to be yanked
+    fn kill(&mut self, start: usize, end: usize) {
+        if start < end {
+            self.killed = self.text[start..end].to_owned();
+            self.text.replace_range(start..end, "");
+            self.cursor = start;
+        }
+    }
+
+    fn prev_char(&self) -> usize {
+        self.text[..self.cursor]
+            .char_indices()
+            .next_back()
+            .map_or(0, |(i, _)| i)
+    }
+
+    fn next_char(&self) -> usize {
+        self.text[self.cursor..]
+            .chars()
+            .next()
+            .map_or(self.cursor, |c| self.cursor + c.len_utf8())
+    }
+
+    fn line_start(&self, pos: usize) -> usize {
+        self.text[..pos].rfind('\n').map_or(0, |i| i + 1)
+    }
+
+    fn line_end(&self, pos: usize) -> usize {
+        self.text[pos..]
+            .find('\n')
+            .map_or(self.text.len(), |i| pos + i)
+    }
+
+    /// Move the cursor to the previous or next line, keeping its column if possible
+    fn move_line(&mut self, up: bool) {
+        let start = self.line_start(self.cursor);
+        let column = self.text[start..self.cursor].width();
+        let target = if up {
+            if start == 0 {
+                return;
+            }
+            self.line_start(start - 1)
+        } else {
+            let end = self.line_end(self.cursor);
+            if end == self.text.len() {
+                return;
+            }
+            end + 1
+        };
+
+        let mut x = 0;
+        self.cursor = self.line_end(target);
+        for (i, c) in self.text[target..self.cursor].char_indices() {
+            if x >= column {
+                self.cursor = target + i;
+                break;
+            }
+            x += c.width().unwrap_or(0);
+        }
+    }
+
+    /// Start of the word before the cursor, skipping whitespace first
+    fn prev_word(&self) -> usize {
+        let before = self.text[..self.cursor].trim_end();
+        before
+            .char_indices()
+            .rev()
+            .find(|(_, c)| c.is_whitespace())
+            .map_or(0, |(i, c)| i + c.len_utf8())
+    }
+
+    /// End of the word after the cursor, skipping whitespace first
+    fn next_word(&self) -> usize {
+        let after = &self.text[self.cursor..];
+        let skipped = after.len() - after.trim_start().len();
+        after[skipped..]
+            .char_indices()
+            .find(|(_, c)| c.is_whitespace())
+            .map_or(self.text.len(), |(i, _)| self.cursor + skipped + i)
+    }
+
+    /// Break the text into lines of `width` columns and at newlines, a wide char never being
+    /// split, and return them with the cursor position as (column, line)
+    pub fn layout(&self, width: usize) -> (Vec<String>, (usize, usize)) {
+        let width = width.max(2);
+        let mut lines = vec![String::new()];
+        let (mut x, mut cursor) = (0, None);
+
+        for (i, c) in self.text.char_indices() {
+            if c == '\n' {
+                if i == self.cursor {
+                    cursor = Some((x, lines.len() - 1));
+                }
+                lines.push(String::new());
+                x = 0;
+                continue;
+            }
+            let char_width = c.width().unwrap_or(0);
+            if x + char_width > width {
+                lines.push(String::new());
+                x = 0;
+            }
+            if i == self.cursor {
+                cursor = Some((x, lines.len() - 1));
+            }
+            lines.last_mut().unwrap().push(c);
+            x += char_width;
+        }
+
+        let cursor = match cursor {
+            Some(cursor) => cursor,
+            // the cursor is at the end, on the next line if the last one is full
+            None if x >= width => {
+                lines.push(String::new());
+                (0, lines.len() - 1)
+            }
+            None => (x, lines.len() - 1),
+        };
+        (lines, cursor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(text: &str) -> InputLine {
+        let mut line = InputLine::default();
+        line.set(text.to_owned());
+        line
+    }
+
+    fn press(line: &mut InputLine, keys: &[Key]) {
+        for key in keys {
+            line.handle_key(*key);
+        }
+    }
+
+    #[test]
+    fn edit_multibyte_chars() {
+        let mut input = line("a👍b");
+        press(&mut input, &[Key::Left, Key::Backspace]);
+        assert_eq!(input.text(), "ab");
+        press(
+            &mut input,
+            &[Key::Char('é'), Key::Char('你'), Key::Left, Key::Delete],
+        );
+        assert_eq!(input.text(), "aéb");
+        press(
+            &mut input,
+            &[Key::Ctrl('a'), Key::Ctrl('k'), Key::Ctrl('y')],
+        );
+        assert_eq!(input.text(), "aéb");
+    }
+
+    #[test]
+    fn kill_and_yank_words() {
+        let mut input = line("héllo wörld");
+        assert_eq!(input.word_before_cursor(), (7, "wörld"));
+        press(&mut input, &[Key::Ctrl('w')]);
+        assert_eq!(input.text(), "héllo ");
+        press(&mut input, &[Key::Ctrl('a'), Key::Ctrl('y')]);
+        assert_eq!(input.text(), "wörldhéllo ");
+    }
+
+    #[test]
+    fn move_between_lines() {
+        let mut input = line("ab\ncd");
+        press(&mut input, &[Key::Home]);
+        assert_eq!(input.layout(10).1, (0, 1));
+        press(&mut input, &[Key::Up, Key::End]);
+        assert_eq!(input.layout(10).1, (2, 0));
+
+        // the column is kept across wide characters
+        let mut input = line("abcd\n你好");
+        press(
+            &mut input,
+            &[Key::Up, Key::Home, Key::Right, Key::Right, Key::Down],
+        );
+        assert_eq!(input.layout(10).1, (2, 1));
+        press(&mut input, &[Key::End, Key::Up]);
+        assert_eq!(input.layout(10).1, (4, 0));
+    }
+
+    #[test]
+    fn layout_cursor_at_line_ends() {
+        assert_eq!(line("abc").layout(4), (vec!["abc".to_owned()], (3, 0)));
+        // a full last line puts the cursor on the next one
+        assert_eq!(
+            line("abcd").layout(4),
+            (vec!["abcd".to_owned(), String::new()], (0, 1))
+        );
+        assert_eq!(
+            line("ab\n").layout(4),
+            (vec!["ab".to_owned(), String::new()], (0, 1))
+        );
+        // on a newline, the cursor stays at the end of its line
+        let mut input = line("ab\ncd");
+        press(&mut input, &[Key::Left, Key::Left, Key::Left]);
+        assert_eq!(input.layout(4).1, (2, 0));
+    }
+
+    #[test]
+    fn layout_wide_chars() {
+        let mut input = line("你好世");
+        assert_eq!(
+            input.layout(4),
+            (vec!["你好".to_owned(), "世".to_owned()], (2, 1))
+        );
+        // a wide char is never split, it goes to the next line with the cursor
+        press(&mut input, &[Key::Left]);
+        assert_eq!(
+            input.layout(5),
+            (vec!["你好".to_owned(), "世".to_owned()], (0, 1))
+        );
+        assert_eq!(line("👍👍").layout(4).1, (0, 1));
+    }
+}
@@ -0,0 +1,102 @@
+//! Detect mentions of the user in messages
+
+use std::ops::Range;
+
+/// Byte ranges of `@username` and `keywords` in `text`, matched case-insensitively as whole words
+pub fn find_mentions(text: &str, username: &str, keywords: &[String]) -> Vec<Range<usize>> {
+    let mention = format!("@{}", username);
+    let mut words: Vec<&str> = keywords.iter().map(String::as_str).collect();
+    words.push(&mention);
+
+    let lower = text.to_lowercase();
+    // lowercasing may change byte lengths, then only exact matches are found
+    let haystack = if lower.len() == text.len() {
+        &lower
+    } else {
+        text
+    };
+
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    for word in words.into_iter().filter(|w| !w.is_empty()) {
+        let word = if lower.len() == text.len() {
+            word.to_lowercase()
+        } else {
+            word.to_owned()
+        };
+        for (start, _) in haystack.match_indices(&word) {
+            let end = start + word.len();
+            if !text.is_char_boundary(start) || !text.is_char_boundary(end) {
+                continue;
+            }
+            let is_boundary = |c: Option<char>| c.is_none_or(|c| !c.is_alphanumeric() && c != '_');
+            if is_boundary(text[..start].chars().next_back())
+                && is_boundary(text[end..].chars().next())
+            {
+                ranges.push(start..end);
+            }
+        }
+    }
+
+    // merge overlapping ranges, e.g. of a keyword equal to the username
+    ranges.sort_by_key(|r| r.start);
+    let mut merged: Vec<Range<usize>> = Vec::new();
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The mentions of `username` and `keywords` found in `text`
+    fn mentions<'a>(text: &'a str, username: &str, keywords: &[&str]) -> Vec<&'a str> {
+        let keywords: Vec<String> = keywords.iter().map(|k| k.to_string()).collect();
+        (find_mentions(text, username, &keywords).into_iter())
+            .map(|range| &text[range])
+            .collect()
+    }
+
+    #[test]
+    fn whole_words_only() {
+        assert_eq!(mentions("hi @bob!", "bob", &[]), ["@bob"]);
+        assert_eq!(mentions("(@bob) @bob", "bob", &[]), ["@bob", "@bob"]);
+        assert!(mentions("@bobby", "bob", &[]).is_empty());
+        assert!(mentions("x@bob", "bob", &[]).is_empty());
+        assert!(mentions("@bob_", "bob", &[]).is_empty());
+        assert!(mentions("é@bob", "bob", &[]).is_empty());
+        assert!(mentions("bob", "bob", &[]).is_empty());
+    }
+
+    #[test]
+    fn case_insensitive() {
+        assert_eq!(mentions("@BOB", "bob", &[]), ["@BOB"]);
+        assert_eq!(mentions("@bob", "Bob", &[]), ["@bob"]);
+        assert_eq!(
+            mentions("Deploy done, deployment later", "bob", &["deploy"]),
+            ["Deploy"]
+        );
+    }
+
+    #[test]
+    fn keywords() {
+        assert_eq!(
+            mentions("rust and go", "bob", &["go", "rust"]),
+            ["rust", "go"]
+        );
+        // empty keywords match nothing, and overlapping matches are merged
+        assert!(mentions("hello", "bob", &[""]).is_empty());
+        assert_eq!(mentions("@bob", "bob", &["@bob", "bob"]), ["@bob"]);
+    }
+
+    #[test]
+    fn exact_when_lowercase_changes_lengths() {
+        // `İ` is lowercased to two characters, of three bytes rather than two
+        assert_eq!(mentions("İ @bob @BOB", "bob", &[]), ["@bob"]);
+        assert_eq!(mentions("İ @BOB", "BOB", &["İ"]), ["İ", "@BOB"]);
+    }
+}
@@ -0,0 +1,265 @@
+//! Searching the messages shown by `TuiApp`, started with `/`
+//!
+//! The query is matched case-insensitively, as plain text or as a regex, and its words like
+//! `from:name` only keep the messages of that user.
+
+use std::ops::Range;
+
+use regex::{Regex, RegexBuilder};
+use tui::style::Style;
+
+use super::entry::Entry;
+use super::input_line::InputLine;
+use super::wrap::StyledString;
+
+#[derive(Debug, Default)]
+pub struct MessageSearch {
+    pub query: InputLine,
+    pub regex: bool,            // whether the query is a regular expression
+    pub editing: bool,          // whether the query is being typed, otherwise n/N jump between hits
+    pub current: Option<usize>, // index of the shown hit in the messages
+    pub error: Option<String>,  // why the query is not a valid regex
+    matcher: Option<Regex>,
+    author: Option<String>, // lowercase
+}
+
+/// Index of the message with id `id` in the history of the server, the last one if repeated
+pub fn find_id(messages: &[Entry], id: usize) -> Option<usize> {
+    messages.iter().rposition(|entry| entry.id() == Some(id))
+}
+
+impl MessageSearch {
+    pub fn new(regex: bool) -> Self {
+        Self {
+            regex,
+            editing: true,
+            ..Self::default()
+        }
+    }
+
+    /// The query without the author filter
+    pub fn pattern(&self) -> String {
+        let words = self.query.text().split(' ');
+        let words: Vec<_> = words.filter(|w| !w.starts_with("from:")).collect();
+        words.join(" ").trim().to_owned()
+    }
+
+    pub fn author(&self) -> Option<&str> {
+        self.author.as_deref()
+    }
+
+    /// Compile the query after it's edited, then show the last hit in `messages`
+    pub fn update(&mut self, messages: &[Entry]) {
+        self.author = (self.query.text().split(' '))
+            .filter_map(|w| w.strip_prefix("from:"))
+            .map(|author| author.trim_start_matches('@').to_lowercase())
+            .next_back();
+
+        let pattern = self.pattern();
+        let source = if self.regex {
+            pattern.clone()
+        } else {
+            regex::escape(&pattern)
+        };
+        self.error = None;
+        self.matcher = match RegexBuilder::new(&source).case_insensitive(true).build() {
+            Ok(matcher) if !pattern.is_empty() => Some(matcher),
+            Ok(_) => None,
+            Err(e) => {
+                self.error = Some(e.to_string());
+                None
+            }
+        };
+        self.current = self.hits(messages).last().copied();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.matcher.is_none() && self.author.is_none()
+    }
+
+    fn is_author(&self, entry: &Entry) -> bool {
+        match &self.author {
+            Some(author) => entry.author().is_some_and(|a| a.to_lowercase() == *author),
+            None => true,
+        }
+    }
+
+    fn is_hit(&self, entry: &Entry) -> bool {
+        !self.is_empty()
+            && self.is_author(entry)
+            && (self.matcher.as_ref()).is_none_or(|m| m.is_match(entry.text()))
+    }
+
+    /// Indices of the matching messages
+    pub fn hits(&self, messages: &[Entry]) -> Vec<usize> {
+        (messages.iter().enumerate())
+            .filter(|(_, entry)| self.is_hit(entry))
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Move to the previous hit if `older`, otherwise the next one, return its index
+    pub fn jump(&mut self, messages: &[Entry], older: bool) -> Option<usize> {
+        let hits = self.hits(messages);
+        let next = match (self.current, older) {
+            (None, _) => hits.last(),
+            (Some(current), true) => hits.iter().rev().find(|i| **i < current),
+            (Some(current), false) => hits.iter().find(|i| **i > current),
+        };
+        if let Some(next) = next {
+            self.current = Some(*next);
+        }
+        next.copied()
+    }
+
+    /// e.g. `2/5` for the second of five hits
+    pub fn position(&self, messages: &[Entry]) -> String {
+        let hits = self.hits(messages);
+        match self.current.and_then(|c| hits.iter().position(|i| *i == c)) {
+            Some(i) => format!("{}/{}", i + 1, hits.len()),
+            None => format!("0/{}", hits.len()),
+        }
+    }
+
+    fn find(&self, text: &str) -> Vec<Range<usize>> {
+        match &self.matcher {
+            Some(matcher) => (matcher.find_iter(text))
+                .map(|m| m.range())
+                .filter(|range| !range.is_empty())
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Patch `style` on the matches in the styled text of `entry`, except its prefix
+    pub fn highlight(
+        &self,
+        entry: &Entry,
+        spans: Vec<StyledString>,
+        style: Style,
+    ) -> Vec<StyledString> {
+        if !self.is_author(entry) {
+            return spans;
+        }
+        let skip = usize::from(entry.author().is_some());
+        let mut highlighted = Vec::new();
+        for (i, (text, span_style)) in spans.into_iter().enumerate() {
+            let matches = if i < skip {
+                Vec::new()
+            } else {
+                self.find(&text)
+            };
+            let mut last = 0;
+            for range in matches {
+                highlighted.push((text[last..range.start].to_owned(), span_style));
+                highlighted.push((text[range.clone()].to_owned(), span_style.patch(style)));
+                last = range.end;
+            }
+            highlighted.push((text[last..].to_owned(), span_style));
+        }
+        highlighted.retain(|(text, _)| !text.is_empty());
+        highlighted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::entry::Voice;
+    use super::*;
+
+    fn message(id: usize, user: &str, text: &str) -> Entry {
+        Entry::user(
+            Some(id),
+            user.to_owned(),
+            Voice::Text,
+            text.to_owned(),
+            Vec::new(),
+        )
+    }
+
+    fn messages() -> Vec<Entry> {
+        vec![
+            message(10, "alice", "Hello there"),
+            Entry::server("hello from the server".to_owned()),
+            message(11, "Bob", "hello alice"),
+            message(12, "alice", "bye"),
+            message(13, "bob", "HELLO again"),
+        ]
+    }
+
+    fn query(query: &str, regex: bool, messages: &[Entry]) -> MessageSearch {
+        let mut search = MessageSearch::new(regex);
+        search.query.set(query.to_owned());
+        search.update(messages);
+        search
+    }
+
+    #[test]
+    fn counts_hits() {
+        let messages = messages();
+        let search = query("hello", false, &messages);
+        assert_eq!(search.hits(&messages), [0, 1, 2, 4]);
+        // the last hit is shown first
+        assert_eq!(search.current, Some(4));
+        assert_eq!(search.position(&messages), "4/4");
+
+        assert!(query("h.llo", false, &messages).hits(&messages).is_empty());
+        assert_eq!(
+            query("h.llo", true, &messages).hits(&messages),
+            [0, 1, 2, 4]
+        );
+        assert_eq!(
+            query("nothing", false, &messages).position(&messages),
+            "0/0"
+        );
+        assert!(query("", false, &messages).hits(&messages).is_empty());
+
+        let invalid = query("(", true, &messages);
+        assert!(invalid.error.is_some());
+        assert!(invalid.hits(&messages).is_empty());
+    }
+
+    #[test]
+    fn jumps_between_hits() {
+        let messages = messages();
+        let mut search = query("hello", false, &messages);
+        assert_eq!(search.jump(&messages, true), Some(2));
+        assert_eq!(search.jump(&messages, true), Some(1));
+        assert_eq!(search.position(&messages), "2/4");
+        assert_eq!(search.jump(&messages, true), Some(0));
+        // no wrapping around at the ends
+        assert_eq!(search.jump(&messages, true), None);
+        assert_eq!(search.current, Some(0));
+        assert_eq!(search.jump(&messages, false), Some(1));
+    }
+
+    #[test]
+    fn finds_ids() {
+        let mut messages = messages();
+        assert_eq!(find_id(&messages, 12), Some(3));
+        assert_eq!(find_id(&messages, 42), None);
+        messages.push(message(12, "alice", "bye"));
+        assert_eq!(find_id(&messages, 12), Some(5));
+    }
+
+    #[test]
+    fn parses_authors() {
+        let messages = messages();
+        let search = query("hello from:bob", false, &messages);
+        assert_eq!(search.pattern(), "hello");
+        assert_eq!(search.author(), Some("bob"));
+        // the author is matched case-insensitively, server messages have none
+        assert_eq!(search.hits(&messages), [2, 4]);
+
+        let search = query("from:@Alice", false, &messages);
+        assert_eq!(search.pattern(), "");
+        assert_eq!(search.author(), Some("alice"));
+        assert_eq!(search.hits(&messages), [0, 3]);
+
+        // the last author wins
+        let search = query("from:bob bye from:alice", false, &messages);
+        assert_eq!(search.pattern(), "bye");
+        assert_eq!(search.author(), Some("alice"));
+        assert_eq!(search.hits(&messages), [3]);
+    }
+}
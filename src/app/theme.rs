@@ -0,0 +1,318 @@
+//! Colors and layout of the terminal UI, loaded from a TOML config file
+//!
+//! ```toml
+//! theme = "light"          # a preset (dark, light) or one of [themes.*]
+//!
+//! [layout]
+//! users-pane = "left"      # left, right or hidden
+//! users-width = 25         # percent of the width
+//!
+//! [themes.solarized]
+//! base = "light"           # the preset to start from
+//! input = "#268bd2"        # color names, 0-255 or #rrggbb, "default" to keep the terminal's
+//! mention = "magenta"
+//! ```
+
+use std::{collections::HashMap, fs, path::Path, str::FromStr};
+
+use serde::de::{self, Deserialize, Deserializer};
+use tui::style::{Color, Modifier, Style};
+
+use crate::error::*;
+
+/// Colors of the UI elements
+#[derive(Clone, Debug, PartialEq)]
+pub struct Theme {
+    pub text: Color,
+    pub server: Color,
+    pub error: Color,
+    pub mention: Color,
+    pub code: Color,
+    pub url: Color,
+    pub input: Color,
+    pub border: Color,
+}
+
+impl Theme {
+    pub const PRESETS: &'static [&'static str] = &["dark", "light"];
+
+    pub fn preset(name: &str) -> Option<Self> {
+        match name {
+            "dark" => Some(Self {
+                text: Color::Reset,
+                server: Color::Reset,
+                error: Color::LightRed,
+                mention: Color::Yellow,
+                code: Color::Cyan,
+                url: Color::Blue,
+                input: Color::Yellow,
+                border: Color::Reset,
+            }),
+            "light" => Some(Self {
+                text: Color::Reset,
+                server: Color::DarkGray,
+                error: Color::Red,
+                mention: Color::Magenta,
+                code: Color::Blue,
+                url: Color::Blue,
+                input: Color::Blue,
+                border: Color::Gray,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn text(&self) -> Style {
+        Style::default().fg(self.text)
+    }
+
+    pub fn server(&self) -> Style {
+        Style::default()
+            .fg(self.server)
+            .add_modifier(Modifier::BOLD)
+    }
+
+    /// Notices of users are told in the color of the server
+    pub fn notice(&self) -> Style {
+        Style::default().fg(self.server)
+    }
+
+    pub fn error(&self) -> Style {
+        Style::default().fg(self.error).add_modifier(Modifier::BOLD)
+    }
+
+    pub fn mention(&self) -> Style {
+        Style::default()
+            .fg(self.mention)
+            .add_modifier(Modifier::BOLD)
+    }
+
+    pub fn code(&self) -> Style {
+        Style::default().fg(self.code)
+    }
+
+    pub fn url(&self) -> Style {
+        Style::default()
+            .fg(self.url)
+            .add_modifier(Modifier::UNDERLINED)
+    }
+
+    pub fn input(&self) -> Style {
+        Style::default().fg(self.input)
+    }
+
+    pub fn border(&self) -> Style {
+        Style::default().fg(self.border)
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::preset("dark").unwrap()
+    }
+}
+
+/// Where the Users pane is
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UsersPane {
+    Left,
+    Right,
+    Hidden,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Layout {
+    pub users_pane: UsersPane,
+    /// Percentage of the width taken by the Users pane
+    pub users_width: u16,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self {
+            users_pane: UsersPane::Right,
+            users_width: 20,
+        }
+    }
+}
+
+/// A theme in the config file, with colors overriding those of a preset
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ThemeFile {
+    base: Option<String>,
+    text: Option<ColorName>,
+    server: Option<ColorName>,
+    error: Option<ColorName>,
+    mention: Option<ColorName>,
+    code: Option<ColorName>,
+    url: Option<ColorName>,
+    input: Option<ColorName>,
+    border: Option<ColorName>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    theme: Option<String>,
+    layout: Layout,
+    themes: HashMap<String, ThemeFile>,
+}
+
+/// All the themes, with the selected one, and the layout
+#[derive(Clone, Debug)]
+pub struct Themes {
+    themes: HashMap<String, Theme>,
+    pub current: Theme,
+    pub current_name: String,
+    pub layout: Layout,
+}
+
+impl Default for Themes {
+    fn default() -> Self {
+        let themes: HashMap<_, _> = Theme::PRESETS
+            .iter()
+            .map(|name| (name.to_string(), Theme::preset(name).unwrap()))
+            .collect();
+        Self {
+            themes,
+            current: Theme::default(),
+            current_name: "dark".to_owned(),
+            layout: Layout::default(),
+        }
+    }
+}
+
+impl Themes {
+    /// Load the config file at `path`, the defaults are used if it does not exist
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e.into()),
+        };
+        let file: ConfigFile = toml::from_str(&content)
+            .map_err(|e| Error::ConfigError(format!("{}: {}", path.display(), e)))?;
+
+        let mut themes = Self {
+            layout: file.layout,
+            ..Self::default()
+        };
+        for (name, theme) in file.themes {
+            let base = theme.base.as_deref().unwrap_or("dark");
+            let mut resolved = Theme::preset(base).ok_or_else(|| {
+                Error::ConfigError(format!("theme `{}`: unknown base `{}`", name, base))
+            })?;
+            let colors = [
+                (&mut resolved.text, theme.text),
+                (&mut resolved.server, theme.server),
+                (&mut resolved.error, theme.error),
+                (&mut resolved.mention, theme.mention),
+                (&mut resolved.code, theme.code),
+                (&mut resolved.url, theme.url),
+                (&mut resolved.input, theme.input),
+                (&mut resolved.border, theme.border),
+            ];
+            for (color, overridden) in colors {
+                if let Some(ColorName(c)) = overridden {
+                    *color = c;
+                }
+            }
+            themes.themes.insert(name, resolved);
+        }
+        if let Some(name) = file.theme {
+            if !themes.select(&name) {
+                return Err(Error::ConfigError(format!("unknown theme `{}`", name)));
+            }
+        }
+        Ok(themes)
+    }
+
+    /// Switch to the theme called `name`, return whether it exists
+    pub fn select(&mut self, name: &str) -> bool {
+        match self.themes.get(name) {
+            Some(theme) => {
+                self.current = theme.clone();
+                self.current_name = name.to_owned();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<_> = self.themes.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+}
+
+/// A color written as a name, an ANSI index, or `#rrggbb`
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct ColorName(Color);
+
+impl FromStr for ColorName {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let name = s.to_lowercase().replace(['_', ' '], "-");
+        let color = match name.as_str() {
+            "default" | "reset" => Color::Reset,
+            "black" => Color::Black,
+            "red" => Color::Red,
+            "green" => Color::Green,
+            "yellow" => Color::Yellow,
+            "blue" => Color::Blue,
+            "magenta" => Color::Magenta,
+            "cyan" => Color::Cyan,
+            "gray" | "grey" => Color::Gray,
+            "dark-gray" | "dark-grey" => Color::DarkGray,
+            "light-red" => Color::LightRed,
+            "light-green" => Color::LightGreen,
+            "light-yellow" => Color::LightYellow,
+            "light-blue" => Color::LightBlue,
+            "light-magenta" => Color::LightMagenta,
+            "light-cyan" => Color::LightCyan,
+            "white" => Color::White,
+            hex if hex.starts_with('#') && hex.len() == 7 && hex.is_ascii() => {
+                let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16);
+                match (channel(1), channel(3), channel(5)) {
+                    (Ok(r), Ok(g), Ok(b)) => Color::Rgb(r, g, b),
+                    _ => return Err(format!("invalid color `{}`", s)),
+                }
+            }
+            index => match index.parse::<u8>() {
+                Ok(i) => Color::Indexed(i),
+                Err(_) => return Err(format!("unknown color `{}`", s)),
+            },
+        };
+        Ok(ColorName(color))
+    }
+}
+
+impl<'de> Deserialize<'de> for ColorName {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_colors() {
+        let parse = |s: &str| s.parse::<ColorName>().map(|c| c.0);
+        assert_eq!(parse("light-blue"), Ok(Color::LightBlue));
+        assert_eq!(parse("#ff8000"), Ok(Color::Rgb(255, 128, 0)));
+        assert_eq!(parse("42"), Ok(Color::Indexed(42)));
+        assert!(parse("#ff80").is_err());
+        assert!(parse("#zz8000").is_err());
+        // multibyte text of the length of a hex color
+        assert!(parse("#é8000").is_err());
+        assert!(parse("#ff800é").is_err());
+    }
+}
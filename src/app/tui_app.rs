@@ -1,59 +1,1032 @@
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
 use std::net::SocketAddr;
 
-use termion::event::Key;
-use termion::{input::TermRead, raw::IntoRawMode, screen::AlternateScreen};
+use chrono::{DateTime, Local, TimeZone};
+use termion::event::{Event, Key, MouseButton, MouseEvent};
+use termion::{
+    input::{MouseTerminal, TermRead},
+    raw::IntoRawMode,
+    screen::AlternateScreen,
+};
 use tokio::{stream::StreamExt, sync::mpsc};
 use tui::{
     backend::TermionBackend,
-    layout::{Constraint, Direction, Layout},
+    layout::{Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
     text::{Span, Spans, Text},
-    widgets::{Block, Borders, List, ListItem, Paragraph, Wrap},
+    widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph},
     Terminal,
 };
 use unicode_width::UnicodeWidthStr;
 
-use crate::{client::ClientInput, error::*, message::User, protocol::ServerCommand};
+use super::buffer::{Buffer, Conversation};
+use super::color::strip_colors;
+use super::command::{self, Command};
+use super::completion::Completion;
+use super::entry::{Entry, MemberChange, Voice};
+use super::history::{History, ReverseSearch};
+use super::input_line::InputLine;
+use super::mention::find_mentions;
+use super::search::{self, MessageSearch};
+use super::theme::UsersPane;
+use super::wrap::{wrap, StyledString};
+use super::{Config, Membership, Notify};
+use crate::{
+    client::ClientInput,
+    error::*,
+    message::{Message, User},
+    protocol::{SearchResult, ServerCommand},
+};
 
 type Tx<T> = mpsc::UnboundedSender<T>;
 type Rx<T> = mpsc::UnboundedReceiver<T>;
 
-type StyledString = (String, Style);
+/// Lines scrolled by a mouse wheel step
+const WHEEL_STEP: usize = 3;
+
+/// Emoji offered by the message menu as quick replies, sent as text quoting the message
+const EMOJI_REPLIES: &[&str] = &["👍", "👎", "😄", "🎉", "👀"];
+
+/// Columns of a message quoted in a reply
+const QUOTE_WIDTH: usize = 40;
+
+/// The menu of a clicked message, to reply to it with text or an emoji
+#[derive(Clone, Copy, Debug)]
+struct MessageMenu {
+    message: usize, // index of the message in the shown buffer
+    area: Rect,
+}
+
+impl MessageMenu {
+    /// Labels of the items, `Text` then the emoji, with the keys selecting them
+    fn items() -> Vec<String> {
+        let text = std::iter::once("r Text".to_owned());
+        let emoji = (EMOJI_REPLIES.iter().enumerate()).map(|(i, e)| format!("{} {}", i + 1, e));
+        text.chain(emoji).collect()
+    }
+
+    /// The item at column `x` of the screen
+    fn item_at(&self, x: u16) -> Option<usize> {
+        let mut left = self.area.x + 1;
+        for (i, item) in Self::items().iter().enumerate() {
+            let right = left + item.width() as u16 + 2;
+            if (left..right).contains(&x) {
+                return Some(i);
+            }
+            left = right;
+        }
+        None
+    }
+}
+
+/// The pane receiving the keys, switched with Tab
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum Focus {
+    #[default]
+    Input,
+    Messages,
+    Users,
+}
+
+impl Focus {
+    fn next(self, backward: bool) -> Self {
+        match (self, backward) {
+            (Focus::Input, false) | (Focus::Users, true) => Focus::Messages,
+            (Focus::Messages, false) | (Focus::Input, true) => Focus::Users,
+            (Focus::Users, false) | (Focus::Messages, true) => Focus::Input,
+        }
+    }
+}
+
+/// What we know about a user seen in the user list
+#[derive(Clone, Debug)]
+struct UserInfo {
+    addr: SocketAddr,
+    joined: DateTime<Local>, // when joined, or first seen online for users already there
+}
 
 /// An app with a clear terminal UI
 #[derive(Default)]
 pub struct TuiApp {
-    input: String,
-    last_input: String,
-    messages: Vec<StyledString>,
+    input: InputLine,
+    history: History,
+    search: Option<ReverseSearch>, // `Some` while searching the history
+    message_search: Option<MessageSearch>, // `Some` while searching the messages
+    multiline: bool,               // whether Enter inserts a newline instead of sending
+    completion: Option<Completion>, // `Some` while cycling through completion candidates
+    buffers: HashMap<Conversation, Buffer>,
+    tabs: Vec<Conversation>, // conversations in the order of the tabs
+    current: usize,          // index of the shown tab
+    mentions: Buffer,        // messages mentioning the user, also in their buffers
+    show_mentions: bool,     // whether the pane shows `mentions` instead of the tab
+    messages_height: usize,  // number of visible lines, updated on drawing
+    messages_width: usize,   // number of visible columns, updated on drawing
+    raw_markup: bool,        // whether the markup of messages is shown as it is
+    focus: Focus,
+    selected_user: usize, // index of the selected user in the Users pane
+    users_offset: usize,  // index of the first user shown in the Users pane
+    user_popup: bool,     // whether the details of the selected user are shown
+    users: HashMap<User, UserInfo>, // all users ever seen online
+    muted: HashSet<User>, // users whose messages are hidden
+    menu: Option<MessageMenu>, // `Some` while the menu of a clicked message is shown
+    // areas of the panes and the message shown on each line, updated on drawing for mouse clicks
+    tabs_area: Rect,
+    messages_area: Rect,
+    users_area: Option<Rect>,
+    input_area: Rect,
+    message_rows: Vec<usize>,
     username: User,
-    users: Vec<StyledString>,
     server_name: String,
+    config: Config,
+}
+
+impl TuiApp {
+    /// The only room of the server, always the first tab
+    fn lobby() -> Conversation {
+        Conversation::Room("lobby".to_owned())
+    }
+
+    /// Open a tab for `conversation` if there's none, return its index
+    fn open(&mut self, conversation: Conversation) -> usize {
+        if let Some(i) = self.tabs.iter().position(|c| *c == conversation) {
+            return i;
+        }
+        let mut buffer = Buffer::default();
+        if let Conversation::Direct(user) = &conversation {
+            buffer.users = vec![self.username.clone(), user.clone()];
+        }
+        self.buffers.insert(conversation.clone(), buffer);
+        self.tabs.push(conversation);
+        self.tabs.len() - 1
+    }
+
+    fn switch_tab(&mut self, i: usize) {
+        if i < self.tabs.len() {
+            self.current = i;
+            self.show_mentions = false;
+            self.buffer_mut().unread = 0;
+        }
+    }
+
+    fn tab_buffer(&self) -> &Buffer {
+        &self.buffers[&self.tabs[self.current]]
+    }
+
+    /// The buffer shown in the pane
+    fn buffer(&self) -> &Buffer {
+        if self.show_mentions {
+            &self.mentions
+        } else {
+            self.tab_buffer()
+        }
+    }
+
+    fn buffer_mut(&mut self) -> &mut Buffer {
+        if self.show_mentions {
+            &mut self.mentions
+        } else {
+            self.buffers.get_mut(&self.tabs[self.current]).unwrap()
+        }
+    }
+
+    /// Add a message to the buffer of `conversation`
+    fn push_to(&mut self, conversation: Conversation, entry: Entry) {
+        let i = self.open(conversation.clone());
+        let shown = !self.show_mentions && i == self.current;
+        self.buffers
+            .get_mut(&conversation)
+            .unwrap()
+            .push(entry, shown);
+    }
+
+    /// Add a message to the shown tab, e.g. the result of a command
+    fn push_message(&mut self, entry: Entry) {
+        let conversation = self.tabs[self.current].clone();
+        self.push_to(conversation, entry);
+    }
+
+    /// Show a message of a user in `conversation`, return whether it mentions us
+    fn push_user_message(
+        &mut self,
+        conversation: Conversation,
+        id: Option<usize>,
+        user: User,
+        message: Message,
+    ) -> bool {
+        if self.muted.contains(&user) {
+            return false;
+        }
+        let voice = match message {
+            Message::Text(_) => Voice::Text,
+            Message::Action(_) => Voice::Action,
+            Message::Notice(_) => Voice::Notice,
+            // only the server tells events, never on behalf of a user
+            Message::System(_) => {
+                self.push_to(conversation, Entry::server(message.text().to_owned()));
+                return false;
+            }
+        };
+        let text = message.text().to_owned();
+        let mentions = if user == self.username {
+            Vec::new()
+        } else {
+            find_mentions(&text, &self.username, &self.config.keywords)
+        };
+        let entry = Entry::user(id, user, voice, text, mentions);
+        let mentioned = entry.is_mention();
+        if mentioned {
+            self.mentions.push(entry.clone(), self.show_mentions);
+        }
+        self.push_to(conversation, entry);
+        mentioned
+    }
+
+    /// Switch to the theme called `name`, or list the themes
+    fn set_theme(&mut self, name: &str) {
+        let themes = &mut self.config.themes;
+        let message = if name.is_empty() {
+            format!(
+                "Theme: {}, available: {}",
+                themes.current_name,
+                themes.names().join(", ")
+            )
+        } else if themes.select(name) {
+            format!("Theme: {}", name)
+        } else {
+            let message = format!("Unknown theme `{}`", name);
+            self.push_message(Entry::error(message));
+            return;
+        };
+        self.push_message(Entry::server(message));
+    }
+
+    fn user_color(&self, user: &str) -> Color {
+        self.config.palette.user_color(user)
+    }
+
+    /// Apply the color settings to a style before rendering it
+    fn style(&self, style: Style) -> Style {
+        if self.config.no_color {
+            strip_colors(style)
+        } else {
+            style
+        }
+    }
+
+    /// Switch the pane between the tab and the mentions
+    fn toggle_mentions(&mut self) {
+        self.show_mentions = !self.show_mentions;
+        let buffer = self.buffer_mut();
+        buffer.scroll = 0;
+        buffer.new_below = 0;
+        buffer.unread = 0;
+    }
+
+    /// The tab bar, with the unread counts
+    fn tab_bar(&self) -> Spans<'static> {
+        let spans: Vec<_> = self
+            .tabs
+            .iter()
+            .enumerate()
+            .map(|(i, conversation)| {
+                let unread = self.buffers[conversation].unread;
+                let label = match unread {
+                    0 => format!(" {}:{} ", i + 1, conversation),
+                    n => format!(" {}:{} ({}) ", i + 1, conversation, n),
+                };
+                let style = if i == self.current && !self.show_mentions {
+                    Style::default().add_modifier(Modifier::REVERSED)
+                } else if unread > 0 {
+                    Style::default().add_modifier(Modifier::BOLD)
+                } else {
+                    Style::default()
+                };
+                Span::styled(label, style)
+            })
+            .collect();
+        Spans::from(spans)
+    }
+
+    fn messages_title(&self) -> Span<'static> {
+        let bold = Style::default().add_modifier(Modifier::BOLD);
+        let name = if self.show_mentions {
+            "Mentions (press Alt-M to go back)".to_owned()
+        } else if self.mentions.unread > 0 {
+            format!(
+                "Messages ({} new mention{}, press Alt-M to show)",
+                self.mentions.unread,
+                if self.mentions.unread > 1 { "s" } else { "" }
+            )
+        } else {
+            "Messages".to_owned()
+        };
+        let name = if self.raw_markup {
+            format!("{} [raw]", name)
+        } else {
+            name
+        };
+        let name = match &self.message_search {
+            Some(search) if !search.editing => format!(
+                "{} [search `{}` {}, n/N to jump, Esc to clear]",
+                name,
+                search.query.text(),
+                search.position(&self.buffer().messages)
+            ),
+            _ => name,
+        };
+        match (self.buffer().scroll, self.buffer().new_below) {
+            (0, _) if self.mentions.unread > 0 => Span::styled(name, bold),
+            (0, _) => Span::raw(name),
+            (_, 0) => Span::raw(format!("{} (scrolled, press End to go back)", name)),
+            (_, n) => Span::styled(
+                format!(
+                    "{} ({} new message{} below)",
+                    name,
+                    n,
+                    if n > 1 { "s" } else { "" }
+                ),
+                bold,
+            ),
+        }
+    }
+
+    /// Ring the bell or send a desktop notification for a mention
+    fn notify(&self, out: &mut impl Write) {
+        let _ = match self.config.notify {
+            Notify::None => return,
+            Notify::Bell => write!(out, "\x07"),
+            Notify::Osc => write!(out, "\x1b]9;Mentioned in {}\x07", self.server_name),
+        };
+        let _ = out.flush();
+    }
+
+    fn wrap(&self, entry: &Entry) -> Vec<Vec<StyledString>> {
+        let theme = &self.config.themes.current;
+        let mut spans = entry.spans(theme, self.config.palette, self.raw_markup);
+        if let Some(search) = &self.message_search {
+            let style = Style::default().add_modifier(Modifier::REVERSED);
+            spans = search.highlight(entry, spans, style);
+        }
+        wrap(&spans, self.messages_width, entry.indent())
+    }
+
+    /// Scrolling stops once the first message is at the top of the pane
+    fn max_scroll(&self) -> usize {
+        let mut height = 0;
+        let messages = &self.buffer().messages;
+        for (i, entry) in messages.iter().enumerate() {
+            height += self.wrap(entry).len();
+            if height >= self.messages_height {
+                return messages.len() - i - 1;
+            }
+        }
+        0
+    }
+
+    /// Wrapped lines of the messages in the pane with the index of their message,
+    /// the last visible message at the bottom
+    fn visible_lines(&self) -> Vec<(usize, Vec<StyledString>)> {
+        let messages = &self.buffer().messages;
+        let end = messages.len() - self.buffer().scroll;
+        let mut lines = Vec::new();
+        for (i, entry) in messages[..end].iter().enumerate().rev() {
+            if lines.len() >= self.messages_height {
+                break;
+            }
+            let mut entry_lines: Vec<_> = self.wrap(entry).into_iter().map(|l| (i, l)).collect();
+            entry_lines.append(&mut lines);
+            lines = entry_lines;
+        }
+        // the top message may be partially visible
+        let start = lines.len().saturating_sub(self.messages_height);
+        lines.split_off(start)
+    }
+
+    fn scroll_up(&mut self, n: usize) {
+        let max_scroll = self.max_scroll();
+        let buffer = self.buffer_mut();
+        buffer.scroll = (buffer.scroll + n).min(max_scroll);
+    }
+
+    fn scroll_down(&mut self, n: usize) {
+        let buffer = self.buffer_mut();
+        buffer.scroll = buffer.scroll.saturating_sub(n);
+        // re-pin to the bottom
+        if buffer.scroll == 0 {
+            buffer.new_below = 0;
+        }
+    }
+
+    /// Handle a key while searching the history, the match being shown in the input
+    fn handle_search_key(&mut self, key: Key) {
+        let search = match self.search.as_mut() {
+            Some(search) => search,
+            None => return,
+        };
+        let found = match key {
+            Key::Ctrl('r') => search.next(&self.history),
+            Key::Char('\n') => {
+                self.search = None;
+                return;
+            }
+            Key::Esc | Key::Ctrl('g') => {
+                let original = std::mem::take(&mut search.original);
+                self.input.set(original);
+                self.search = None;
+                return;
+            }
+            Key::Backspace => {
+                search.query.pop();
+                search.found = None;
+                search.update(&self.history)
+            }
+            Key::Char(c) => {
+                search.query.push(c);
+                search.update(&self.history)
+            }
+            // accept the match and edit it
+            key => {
+                self.search = None;
+                self.input.handle_key(key);
+                return;
+            }
+        };
+        if let Some(found) = found {
+            self.input.set(found.to_owned());
+        }
+    }
+
+    /// Complete the word before the cursor, or cycle through the candidates,
+    /// return whether there's anything to complete
+    fn complete(&mut self, backward: bool) -> bool {
+        match self.completion.as_mut() {
+            Some(completion) => {
+                completion.cycle(&mut self.input, backward);
+                true
+            }
+            None => {
+                let users = self.tab_buffer().users.clone();
+                let rooms: Vec<_> = self
+                    .tabs
+                    .iter()
+                    .filter_map(|c| match c {
+                        Conversation::Room(room) => Some(room.clone()),
+                        Conversation::Direct(_) => None,
+                    })
+                    .collect();
+                let completion = Completion::start(&mut self.input, &users, &rooms);
+                let completed = completion.is_some();
+                self.completion = completion.filter(Completion::is_ambiguous);
+                completed
+            }
+        }
+    }
+
+    /// The user selected in the Users pane
+    fn selected_user(&self) -> Option<&User> {
+        self.tab_buffer().users.get(self.selected_user)
+    }
+
+    fn select_user(&mut self, up: bool) {
+        let len = self.tab_buffer().users.len();
+        self.selected_user = if up {
+            self.selected_user.saturating_sub(1)
+        } else {
+            (self.selected_user + 1).min(len.saturating_sub(1))
+        };
+    }
+
+    /// Remember the addresses of the online users, and when they were first seen
+    fn update_users(&mut self, users: Vec<(User, SocketAddr)>) {
+        let now = Local::now();
+        for (user, addr) in &users {
+            let info = self.users.entry(user.clone()).or_insert(UserInfo {
+                addr: *addr,
+                joined: now,
+            });
+            info.addr = *addr;
+        }
+        let lobby = self.buffers.get_mut(&Self::lobby()).unwrap();
+        lobby.users = users.into_iter().map(|(n, _a)| n).collect();
+    }
+
+    /// Follow a user changing their name, in the tabs, mutes and our own name
+    fn rename(&mut self, old: User, new: User) {
+        if old == self.username {
+            self.username = new.clone();
+            for (conversation, buffer) in self.buffers.iter_mut() {
+                if let Conversation::Direct(_) = conversation {
+                    buffer.users[0] = new.clone();
+                }
+            }
+        }
+        let (old_tab, new_tab) = (
+            Conversation::Direct(old.clone()),
+            Conversation::Direct(new.clone()),
+        );
+        if !self.buffers.contains_key(&new_tab) {
+            if let Some(mut buffer) = self.buffers.remove(&old_tab) {
+                buffer.users[1] = new.clone();
+                self.buffers.insert(new_tab.clone(), buffer);
+                for tab in self.tabs.iter_mut().filter(|tab| **tab == old_tab) {
+                    *tab = new_tab.clone();
+                }
+            }
+        }
+        if self.muted.remove(&old) {
+            self.muted.insert(new.clone());
+        }
+        if let Some(info) = self.users.remove(&old) {
+            self.users.insert(new, info);
+        }
+    }
+
+    /// Follow a user joining, leaving or changing their name at `time`, and tell it as configured
+    fn change_member(&mut self, change: MemberChange, addr: SocketAddr, time: DateTime<Local>) {
+        match &change {
+            MemberChange::Joined(user) => {
+                self.users
+                    .insert(user.clone(), UserInfo { addr, joined: time });
+            }
+            MemberChange::Left(_) => {}
+            MemberChange::Renamed(old, new) => self.rename(old.clone(), new.clone()),
+        }
+
+        if self.config.membership == Membership::Hide {
+            return;
+        }
+        let lobby = self.buffers.get_mut(&Self::lobby()).unwrap();
+        let collapsed = self.config.membership == Membership::Collapse
+            && (lobby.messages.last_mut()).is_some_and(|e| e.add_change(change.clone()));
+        if !collapsed {
+            self.push_to(Self::lobby(), Entry::membership(change));
+        }
+    }
+
+    fn is_online(&self, user: &str) -> bool {
+        self.buffers[&Self::lobby()].users.iter().any(|u| u == user)
+    }
+
+    /// Lines of the popup showing the details of the selected user
+    fn user_details(&self) -> Option<Vec<Spans<'static>>> {
+        let user = self.selected_user()?;
+        let bold = Style::default().add_modifier(Modifier::BOLD);
+        let field = |name: &str, value: String| {
+            Spans::from(vec![
+                Span::styled(format!("{:>9}: ", name), bold),
+                Span::raw(value),
+            ])
+        };
+        let info = self.users.get(user);
+        let mut lines = vec![
+            Spans::from(Span::styled(
+                user.clone(),
+                self.style(bold.fg(self.user_color(user))),
+            )),
+            Spans::default(),
+            field(
+                "Address",
+                info.map_or("unknown".to_owned(), |i| i.addr.to_string()),
+            ),
+            field(
+                "Presence",
+                if self.is_online(user) {
+                    "online"
+                } else {
+                    "offline"
+                }
+                .to_owned(),
+            ),
+            field(
+                "Joined",
+                info.map_or("unknown".to_owned(), |i| {
+                    i.joined.format("%Y-%m-%d %H:%M:%S").to_string()
+                }),
+            ),
+        ];
+        if self.muted.contains(user) {
+            lines.push(field("Muted", "yes".to_owned()));
+        }
+        lines.push(Spans::default());
+        let mut actions = vec![Span::styled("m", bold), Span::raw(" mention")];
+        if *user != self.username {
+            let mute = if self.muted.contains(user) {
+                " unmute"
+            } else {
+                " mute"
+            };
+            actions.splice(0..0, vec![Span::styled("d", bold), Span::raw(" message  ")]);
+            actions.extend(vec![
+                Span::raw("  "),
+                Span::styled("u", bold),
+                Span::raw(mute),
+            ]);
+        }
+        lines.push(Spans::default());
+        lines.push(Spans::from(actions));
+        Some(lines)
+    }
+
+    /// Handle a key while the Users pane is focused, return whether it's consumed
+    fn handle_users_key(&mut self, key: Key) -> bool {
+        let user = match self.selected_user() {
+            Some(user) => user.clone(),
+            None => return false,
+        };
+        match key {
+            Key::Up | Key::Char('k') if !self.user_popup => self.select_user(true),
+            Key::Down | Key::Char('j') if !self.user_popup => self.select_user(false),
+            Key::Char('\n') => self.user_popup = !self.user_popup,
+            // start a direct conversation
+            Key::Char('d') if user != self.username => {
+                let i = self.open(Conversation::Direct(user));
+                self.switch_tab(i);
+                self.user_popup = false;
+                self.focus = Focus::Input;
+            }
+            // mention in the input
+            Key::Char('m') => {
+                if !self.input.word_before_cursor().1.is_empty() {
+                    self.input.insert(' ');
+                }
+                for c in format!("@{} ", user).chars() {
+                    self.input.insert(c);
+                }
+                self.user_popup = false;
+                self.focus = Focus::Input;
+            }
+            // mute locally
+            Key::Char('u') if user != self.username => {
+                let message = if self.muted.remove(&user) {
+                    format!("Unmuted {}", user)
+                } else {
+                    self.muted.insert(user.clone());
+                    format!("Muted {}, their new messages won't be shown", user)
+                };
+                self.push_message(Entry::server(message));
+            }
+            // not to ourselves
+            Key::Char('d') | Key::Char('u') => {}
+            _ => return false,
+        }
+        true
+    }
+
+    fn border_style(&self, focus: Focus) -> Style {
+        let theme = &self.config.themes.current;
+        if self.focus == focus {
+            self.style(theme.input().add_modifier(Modifier::BOLD))
+        } else {
+            self.style(theme.border())
+        }
+    }
+
+    /// The input line shown, the query while typing a message search
+    fn shown_input(&self) -> &InputLine {
+        match &self.message_search {
+            Some(search) if search.editing => &search.query,
+            _ => &self.input,
+        }
+    }
+
+    /// Scroll so that the message at `i` in the pane is at the bottom
+    fn show_message(&mut self, i: usize) {
+        let buffer = self.buffer_mut();
+        buffer.scroll = buffer.messages.len().saturating_sub(i + 1);
+        self.scroll_up(0);
+        self.scroll_down(0);
+    }
+
+    /// Handle a key while typing the query of a message search
+    fn handle_message_search_key(&mut self, key: Key, input_tx: &Tx<ClientInput>) {
+        let mut search = match self.message_search.take() {
+            Some(search) => search,
+            None => return,
+        };
+        match key {
+            Key::Char('\n') => {
+                search.editing = false;
+                self.focus = Focus::Messages;
+                // nothing in the buffer, ask the server
+                if search.current.is_none() && !search.is_empty() && search.error.is_none() {
+                    self.push_message(Entry::server(format!(
+                        "No match here, searching the server for `{}`",
+                        search.query.text()
+                    )));
+                    let room = match &self.tabs[self.current] {
+                        Conversation::Room(room) => Some(room.clone()),
+                        Conversation::Direct(_) => None,
+                    };
+                    input_tx
+                        .send(ClientInput::Search {
+                            query: search.pattern(),
+                            room,
+                            author: search.author().map(str::to_owned),
+                        })
+                        .unwrap();
+                }
+            }
+            Key::Esc | Key::Ctrl('g') => return,
+            Key::Backspace if search.query.is_empty() => return,
+            Key::Ctrl('r') => {
+                search.regex = !search.regex;
+                search.update(&self.buffer().messages);
+            }
+            key => {
+                search.query.handle_key(key);
+                search.update(&self.buffer().messages);
+            }
+        }
+        let current = search.current;
+        self.message_search = Some(search);
+        if let Some(i) = current {
+            self.show_message(i);
+        }
+    }
+
+    /// List the messages found on the server, and show the best one if it's in a buffer
+    fn show_results(&mut self, query: String, results: Vec<SearchResult>) {
+        self.push_message(Entry::server(format!(
+            "{} result{} for `{}` on the server",
+            results.len(),
+            if results.len() == 1 { "" } else { "s" },
+            query
+        )));
+        for result in &results {
+            let time = Local.timestamp(result.time, 0);
+            self.push_message(Entry::server(format!(
+                "#{} [{}, {}] {}",
+                result.id,
+                result.user,
+                time.format("%Y-%m-%d %H:%M"),
+                result.message
+            )));
+        }
+
+        let best = match results.first() {
+            Some(best) => best,
+            None => return,
+        };
+        let conversation = Conversation::Room(best.room.clone());
+        let found = (self.buffers.get(&conversation))
+            .and_then(|buffer| search::find_id(&buffer.messages, best.id));
+        if let (Some(i), Some(tab)) = (found, self.tabs.iter().position(|c| *c == conversation)) {
+            self.switch_tab(tab);
+            if let Some(search) = &mut self.message_search {
+                search.current = Some(i);
+            }
+            self.show_message(i);
+        }
+    }
+
+    /// Jump to the previous hit of the message search if `older`, otherwise the next one
+    fn jump(&mut self, older: bool) {
+        let mut search = match self.message_search.take() {
+            Some(search) => search,
+            None => return,
+        };
+        let hit = search.jump(&self.buffer().messages, older);
+        self.message_search = Some(search);
+        if let Some(i) = hit {
+            self.show_message(i);
+        }
+    }
+
+    fn input_title(&self) -> String {
+        if let Some(search) = self.message_search.as_ref().filter(|s| s.editing) {
+            let state = match &search.error {
+                Some(_) => "invalid regex".to_owned(),
+                None => search.position(&self.buffer().messages),
+            };
+            return format!(
+                "Search messages{} {} (from:name to filter, Ctrl-R for {}, Enter to browse)",
+                if search.regex { " by regex" } else { "" },
+                state,
+                if search.regex { "text" } else { "regex" }
+            );
+        }
+        match &self.search {
+            Some(search) => format!(
+                "{} ({}reverse-i-search: `{}`)",
+                self.username,
+                if search.failed { "failed " } else { "" },
+                search.query
+            ),
+            None if self.multiline => format!(
+                "{} (multiline, Alt-Enter to send, Ctrl-O to toggle)",
+                self.username
+            ),
+            None => self.username.clone(),
+        }
+    }
+
+    fn page(&self) -> usize {
+        self.messages_height.saturating_sub(1).max(1)
+    }
+
+    /// Run a client command, return whether to exit
+    fn run_command(&mut self, command: Command, input_tx: &Tx<ClientInput>) -> bool {
+        match command {
+            Command::Help(name) => {
+                let help = command::help(name.as_deref(), true).join("\n");
+                self.push_message(Entry::server(help));
+            }
+            Command::Exit => {
+                input_tx.send(ClientInput::Exit).unwrap();
+                return true;
+            }
+            Command::Nick(name) => input_tx.send(ClientInput::SetName(name)).unwrap(),
+            Command::Join(room) if Conversation::Room(room.clone()) == Self::lobby() => {
+                let i = self.open(Self::lobby());
+                self.switch_tab(i);
+            }
+            Command::Join(room) => {
+                let message = format!("No such room `#{}`, there's only {}", room, Self::lobby());
+                self.push_message(Entry::error(message));
+            }
+            Command::Me(action) => {
+                input_tx
+                    .send(self.outgoing(Message::Action(action)))
+                    .unwrap();
+            }
+            Command::Msg(user, text) => {
+                let i = self.open(Conversation::Direct(user.clone()));
+                self.switch_tab(i);
+                input_tx
+                    .send(ClientInput::Direct(user, Message::Text(text)))
+                    .unwrap();
+            }
+            Command::Clear => self.buffer_mut().clear(),
+            Command::Raw => self.raw_markup = !self.raw_markup,
+            Command::Theme(name) => self.set_theme(name.as_deref().unwrap_or("")),
+            Command::Fuck => self.push_message(Entry::error("What's your problem?".to_string())),
+        }
+        false
+    }
+
+    /// A message to send to the conversation of the shown tab
+    fn outgoing(&self, message: Message) -> ClientInput {
+        match &self.tabs[self.current] {
+            Conversation::Direct(user) => ClientInput::Direct(user.clone(), message),
+            Conversation::Room(_) => ClientInput::Message(message),
+        }
+    }
+
+    /// The tab whose label is at column `x` of the tab bar
+    fn tab_at(&self, x: u16) -> Option<usize> {
+        let mut right = self.tabs_area.x;
+        for (i, span) in self.tab_bar().0.iter().enumerate() {
+            right += span.width() as u16;
+            if x < right {
+                return Some(i);
+            }
+        }
+        None
+    }
+
+    /// Quote the first line of a message of a user, shortened
+    fn quote(entry: &Entry) -> Option<String> {
+        let user = entry.author()?;
+        let line = entry.text().lines().next().unwrap_or("");
+        let mut quoted = String::new();
+        for c in line.chars() {
+            if quoted.width() >= QUOTE_WIDTH {
+                quoted.push('…');
+                break;
+            }
+            quoted.push(c);
+        }
+        Some(format!("> {}: {}", user, quoted))
+    }
+
+    /// Handle a click at column `x` and row `y` of the screen, counted from 0
+    fn click(&mut self, x: u16, y: u16, input_tx: &Tx<ClientInput>) -> Option<()> {
+        let inside = |area: Rect| {
+            (area.x..area.x + area.width).contains(&x)
+                && (area.y..area.y + area.height).contains(&y)
+        };
+
+        if let Some(menu) = self.menu.take() {
+            if inside(menu.area) {
+                return self.menu_action(menu.message, menu.item_at(x)?, input_tx);
+            }
+        }
+        if inside(self.tabs_area) {
+            if let Some(i) = self.tab_at(x) {
+                self.switch_tab(i);
+            }
+        } else if inside(self.input_area) {
+            self.focus = Focus::Input;
+        } else if let Some(users_area) = self.users_area.filter(|area| inside(*area)) {
+            // mention the clicked user
+            let row = (y - users_area.y).wrapping_sub(1) as usize;
+            let i = self.users_offset + row;
+            if row < users_area.height.saturating_sub(2) as usize
+                && i < self.tab_buffer().users.len()
+            {
+                self.selected_user = i;
+                self.handle_users_key(Key::Char('m'));
+            }
+        } else if inside(self.messages_area) {
+            // open the menu of the clicked message
+            let row = (y - self.messages_area.y).wrapping_sub(1) as usize;
+            let message = *self.message_rows.get(row)?;
+            self.buffer().messages[message].author()?;
+            let width: usize = MessageMenu::items().iter().map(|i| i.width() + 2).sum();
+            let width = (width as u16 + 2).min(self.messages_area.width);
+            let right = self.messages_area.x + self.messages_area.width;
+            let below = y + 4 <= self.messages_area.y + self.messages_area.height;
+            let area = Rect::new(
+                x.min(right - width),
+                if below { y + 1 } else { y.saturating_sub(3) },
+                width,
+                3,
+            );
+            self.menu = Some(MessageMenu { message, area });
+        }
+        Some(())
+    }
+
+    /// Reply to the message at `message` in the shown buffer, by quoting it in the input for
+    /// item 0, or by sending an emoji under the quote
+    fn menu_action(
+        &mut self,
+        message: usize,
+        item: usize,
+        input_tx: &Tx<ClientInput>,
+    ) -> Option<()> {
+        let quote = Self::quote(self.buffer().messages.get(message)?)?;
+        self.focus = Focus::Input;
+        match item {
+            0 => self.input.set(format!("{}\n", quote)),
+            i => {
+                let emoji = EMOJI_REPLIES.get(i - 1)?;
+                let input = self.outgoing(Message::Text(format!("{}\n{}", quote, emoji)));
+                input_tx.send(input).unwrap();
+            }
+        }
+        Some(())
+    }
+
+    /// Handle a key while the message menu is shown, return whether it's consumed,
+    /// the menu is closed anyway
+    fn handle_menu_key(&mut self, key: Key, input_tx: &Tx<ClientInput>) -> bool {
+        let menu = match self.menu.take() {
+            Some(menu) => menu,
+            None => return false,
+        };
+        match key {
+            Key::Char('r') => {
+                self.menu_action(menu.message, 0, input_tx);
+            }
+            Key::Char(c @ '1'..='9') => {
+                self.menu_action(menu.message, c as usize - '0' as usize, input_tx);
+            }
+            Key::Esc => {}
+            _ => return false,
+        }
+        true
+    }
 }
 
 /// Events from both stdin and the client that the tui app must respond,
 /// generated by different tasks
 #[derive(Debug)]
 enum AppEvent {
-    Key(termion::event::Key),          // stdin: key pressed
-    Message(StyledString),             // msg_rx: new message to show
-    UserList(Vec<(User, SocketAddr)>), // msg_rx: updated user list
-    ServerName(String),                // msg_rx: server name to show
+    Key(termion::event::Key),                          // stdin: key pressed
+    Mouse(MouseEvent),                                 // stdin: mouse clicked or scrolled
+    Message(Entry),                                    // msg_rx: new message to show
+    UserMessage(User, Message, usize),                 // msg_rx: new message of a user to show
+    DirectMessage(User, User, Message),                // msg_rx: new direct message to show
+    UserList(Vec<(User, SocketAddr)>),                 // msg_rx: updated user list
+    Member(MemberChange, SocketAddr, DateTime<Local>), // msg_rx: a user joined, left or was renamed
+    SearchResults(String, Vec<SearchResult>),          // msg_rx: messages found on the server
+    ServerName(String),                                // msg_rx: server name to show
 }
 
 impl super::App for TuiApp {
-    fn start(input_tx: Tx<ClientInput>, mut msg_rx: Rx<ServerCommand>, name: &str) -> Result<()> {
+    fn start(
+        input_tx: Tx<ClientInput>,
+        mut msg_rx: Rx<ServerCommand>,
+        name: &str,
+        config: Config,
+    ) -> Result<()> {
         // init tui
         let stdout = std::io::stdout().into_raw_mode()?;
+        let stdout = MouseTerminal::from(stdout);
         let stdout = AlternateScreen::from(stdout);
         let backend = TermionBackend::new(stdout);
         let mut terminal = Terminal::new(backend)?;
 
         let mut app = Self {
             username: name.to_owned(),
+            history: History::load(),
+            config,
             server_name: "Chat".to_string(),
             ..Self::default()
         };
+        app.open(Self::lobby());
 
         // TODO: why does TOKIO mpsc channel fail to work perperly?
         let (event_tx, event_rx) = std::sync::mpsc::channel();
@@ -65,63 +1038,89 @@ impl super::App for TuiApp {
             tokio::spawn(async move {
                 while let Some(command) = msg_rx.next().await {
                     match command {
-                        ServerCommand::UserMessage(user, message) => {
-                            let msg = format!(
-                                "[{}, {}] {}",
-                                user,
-                                chrono::Local::now().format("%H:%M:%S"),
-                                message
-                            );
+                        ServerCommand::UserMessage(user, message, id) => {
+                            event_tx
+                                .send(AppEvent::UserMessage(user, message, id))
+                                .unwrap();
+                        }
+                        ServerCommand::DirectMessage(from, to, message) => {
                             event_tx
-                                .send(AppEvent::Message((msg, Style::default())))
+                                .send(AppEvent::DirectMessage(from, to, message))
                                 .unwrap();
                         }
                         ServerCommand::ServerMessage(message) => {
-                            let msg = format!("=> {}", message);
                             event_tx
-                                .send(AppEvent::Message((
-                                    msg,
-                                    Style::default().add_modifier(Modifier::BOLD),
-                                )))
+                                .send(AppEvent::Message(Entry::server(message.text().to_owned())))
                                 .unwrap();
                         }
                         ServerCommand::UserList(users) => {
                             event_tx.send(AppEvent::UserList(users)).unwrap();
                         }
+                        ServerCommand::UserJoined { user, addr, time } => {
+                            let change = MemberChange::Joined(user);
+                            let time = Local.timestamp(time, 0);
+                            event_tx.send(AppEvent::Member(change, addr, time)).unwrap();
+                        }
+                        ServerCommand::UserLeft { user, addr, time } => {
+                            let change = MemberChange::Left(user);
+                            let time = Local.timestamp(time, 0);
+                            event_tx.send(AppEvent::Member(change, addr, time)).unwrap();
+                        }
+                        ServerCommand::UserRenamed {
+                            old,
+                            new,
+                            addr,
+                            time,
+                        } => {
+                            let change = MemberChange::Renamed(old, new);
+                            let time = Local.timestamp(time, 0);
+                            event_tx.send(AppEvent::Member(change, addr, time)).unwrap();
+                        }
+                        ServerCommand::SearchResults(query, results) => {
+                            event_tx
+                                .send(AppEvent::SearchResults(query, results))
+                                .unwrap();
+                        }
                         ServerCommand::Error(message) => {
-                            let msg = format!("=> Error: {}", message);
                             event_tx
-                                .send(AppEvent::Message((
-                                    msg,
-                                    Style::default()
-                                        .add_modifier(Modifier::BOLD)
-                                        .fg(Color::LightRed),
-                                )))
+                                .send(AppEvent::Message(Entry::error(format!(
+                                    "Error: {}",
+                                    message
+                                ))))
                                 .unwrap();
                         }
 
                         ServerCommand::ServerName(name) => {
                             event_tx.send(AppEvent::ServerName(name)).unwrap();
                         }
+                        // negotiated by the client before the app starts
+                        ServerCommand::Encoding(_) => {}
+                        // only sent to linked servers
+                        ServerCommand::Relay(_) => {}
                     }
                 }
             })
         };
 
-        // receive keyboard input from stdin,
+        // receive keyboard and mouse input from stdin,
         // then wrap them into `AppEvent` and send through `event_tx`
+        // (reading stdin blocks, so run it on a blocking thread instead of starving the runtime)
         let _key_task = {
             // let event_tx = event_tx.clone();
-            tokio::spawn(async move {
-                let mut keys = std::io::stdin().keys();
-                while let Some(Ok(key)) = keys.next() {
-                    let _ = event_tx.send(AppEvent::Key(key));
+            tokio::task::spawn_blocking(move || {
+                let mut events = std::io::stdin().events();
+                while let Some(Ok(event)) = events.next() {
+                    let _ = match event {
+                        Event::Key(key) => event_tx.send(AppEvent::Key(key)),
+                        Event::Mouse(mouse) => event_tx.send(AppEvent::Mouse(mouse)),
+                        Event::Unsupported(_) => Ok(()),
+                    };
                 }
             })
         };
 
-        // tui task, `app` & `event_rx` moved
-        let _tui_task = tokio::spawn(async move {
+        // tui task, `app` & `event_rx` moved, blocks on `event_rx` as well
+        let _tui_task = tokio::task::spawn_blocking(move || {
             let mut exited = false;
             loop {
                 // draw tui
@@ -130,23 +1129,53 @@ impl super::App for TuiApp {
                         // chunk represents an area
                         /*
                         |        chunks[0]        |
-                        | schunks[0] | schunks[1] | <- chunks[1]
-                        |        chunks[2]        |
+                        |        chunks[1]        | <- tabs
+                        | schunks[0] | schunks[1] | <- chunks[2]
+                        |        chunks[3]        |
                         */
+                        // grow the input area with its lines, up to half of the terminal
+                        let input_lines = app
+                            .shown_input()
+                            .layout(f.size().width.saturating_sub(4) as usize)
+                            .0
+                            .len();
+                        let input_height = (input_lines as u16 + 2).min(f.size().height / 2).max(3);
                         let chunks = Layout::default()
                             .margin(1)
                             .direction(Direction::Vertical)
                             .constraints([
                                 Constraint::Max(2),
-                                Constraint::Percentage(70),
-                                Constraint::Percentage(20),
+                                Constraint::Length(1),
+                                Constraint::Min(3),
+                                Constraint::Length(input_height),
                             ])
                             .split(f.size());
 
+                        // the messages and the users, in the order of the layout
+                        let layout = &app.config.themes.layout;
+                        let users_width = layout.users_width.min(100);
                         let schunks = Layout::default()
                             .direction(Direction::Horizontal)
-                            .constraints([Constraint::Percentage(80), Constraint::Percentage(20)])
-                            .split(chunks[1]);
+                            .constraints([
+                                Constraint::Percentage(100 - users_width),
+                                Constraint::Percentage(users_width),
+                            ])
+                            .split(chunks[2]);
+                        let (messages_area, users_area) = match layout.users_pane {
+                            UsersPane::Right => (schunks[0], Some(schunks[1])),
+                            UsersPane::Left => {
+                                let schunks = Layout::default()
+                                    .direction(Direction::Horizontal)
+                                    .constraints([
+                                        Constraint::Percentage(users_width),
+                                        Constraint::Percentage(100 - users_width),
+                                    ])
+                                    .split(chunks[2]);
+                                (schunks[1], Some(schunks[0]))
+                            }
+                            UsersPane::Hidden => (chunks[2], None),
+                        };
+                        let theme = app.config.themes.current.clone();
 
                         // --------
                         let help_widget = Paragraph::new(Text::from(Spans::from(vec![
@@ -165,48 +1194,186 @@ impl super::App for TuiApp {
                         f.render_widget(help_widget, chunks[0]);
 
                         // --------
-                        let messages: Vec<_> = app
-                            .messages
-                            .iter()
-                            .rev()
-                            .take((chunks[1].height - 2) as usize) // take visiable ones
-                            .rev()
-                            .map(|(c, s)| ListItem::new(Span::styled(c, s.clone())))
-                            .collect();
-                        let message_widget = List::new(messages)
-                            .block(Block::default().borders(Borders::ALL).title("Messages"));
-                        f.render_widget(message_widget, schunks[0]);
+                        f.render_widget(Paragraph::new(app.tab_bar()), chunks[1]);
+                        app.tabs_area = chunks[1];
+                        app.messages_area = messages_area;
+                        app.users_area = users_area;
+                        app.input_area = chunks[3];
 
                         // --------
-                        let users: Vec<_> = app
-                            .users
-                            .iter()
-                            .map(|(c, s)| ListItem::new(Span::styled(c, s.clone())))
+                        app.messages_height = messages_area.height.saturating_sub(2) as usize;
+                        app.messages_width = messages_area.width.saturating_sub(2) as usize;
+                        app.scroll_up(0); // clamp the scroll offset for the new size
+                        let mut rows = Vec::new();
+                        let messages: Vec<_> = app
+                            .visible_lines()
+                            .into_iter()
+                            .map(|(i, line)| {
+                                rows.push(i);
+                                Spans::from(
+                                    line.into_iter()
+                                        .map(|(c, s)| Span::styled(c, app.style(s)))
+                                        .collect::<Vec<_>>(),
+                                )
+                            })
                             .collect();
-                        let users_widget = List::new(users).block(
+                        app.message_rows = rows;
+                        let message_widget = Paragraph::new(messages).block(
                             Block::default()
                                 .borders(Borders::ALL)
-                                .title(format!("{} Users", app.users.len())),
+                                .border_style(app.border_style(Focus::Messages))
+                                .title(app.messages_title()),
                         );
-                        f.render_widget(users_widget, schunks[1]);
+                        f.render_widget(message_widget, messages_area);
+
+                        // --------
+                        if let Some(users_area) = users_area {
+                            let count = app.tab_buffer().users.len();
+                            app.selected_user = app.selected_user.min(count.saturating_sub(1));
+                            // scroll here rather than in `List`, so that clicks know the offset,
+                            // keeping the selected user in view
+                            let height = users_area.height.saturating_sub(2) as usize;
+                            if app.focus == Focus::Users {
+                                app.users_offset = (app.users_offset)
+                                    .min(app.selected_user)
+                                    .max((app.selected_user + 1).saturating_sub(height));
+                            }
+                            app.users_offset = app.users_offset.min(count.saturating_sub(height));
+                            let users = &app.tab_buffer().users;
+                            let items: Vec<_> = (users.iter().skip(app.users_offset).take(height))
+                                .map(|user| {
+                                    let mut style = Style::default().fg(app.user_color(user));
+                                    let mut name = user.clone();
+                                    if app.muted.contains(user) {
+                                        style = style.add_modifier(Modifier::DIM);
+                                        name.push_str(" (muted)");
+                                    }
+                                    ListItem::new(Span::styled(name, app.style(style)))
+                                })
+                                .collect();
+                            let users_widget = List::new(items)
+                                .block(
+                                    Block::default()
+                                        .borders(Borders::ALL)
+                                        .border_style(app.border_style(Focus::Users))
+                                        .title(format!("{} Users", users.len())),
+                                )
+                                .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+                            let mut state = ListState::default();
+                            if app.focus == Focus::Users {
+                                state.select(Some(app.selected_user - app.users_offset));
+                            }
+                            f.render_stateful_widget(users_widget, users_area, &mut state);
+
+                            // details of the selected user, over the messages
+                            if let Some(lines) = app.user_details().filter(|_| app.user_popup) {
+                                let width = lines.iter().map(Spans::width).max().unwrap_or(0);
+                                let width = (width as u16 + 4).min(messages_area.width);
+                                let height = (lines.len() as u16 + 2).min(messages_area.height);
+                                let area = Rect::new(
+                                    messages_area.x + (messages_area.width - width) / 2,
+                                    messages_area.y + (messages_area.height - height) / 2,
+                                    width,
+                                    height,
+                                );
+                                f.render_widget(Clear, area);
+                                f.render_widget(
+                                    Paragraph::new(lines).block(
+                                        Block::default()
+                                            .borders(Borders::ALL)
+                                            .border_style(app.border_style(Focus::Users))
+                                            .title("User (Esc to close)"),
+                                    ),
+                                    area,
+                                );
+                            }
+                        }
 
                         // --------
-                        let input_widget = Paragraph::new(app.input.as_ref())
-                            .style(Style::default().fg(Color::Yellow))
-                            .wrap(Wrap { trim: true })
-                            .block(
+                        let input_height = chunks[3].height.saturating_sub(2) as usize;
+                        let (lines, (x, y)) = app
+                            .shown_input()
+                            .layout(chunks[3].width.saturating_sub(2) as usize);
+                        // keep the cursor line visible
+                        let offset = (y + 1).saturating_sub(input_height);
+                        let lines: Vec<_> =
+                            lines.into_iter().skip(offset).map(Spans::from).collect();
+                        let input_widget =
+                            Paragraph::new(lines).style(app.style(theme.input())).block(
                                 Block::default()
                                     .borders(Borders::ALL)
-                                    .title(app.username.clone()),
+                                    .border_style(app.border_style(Focus::Input))
+                                    .title(app.input_title()),
                             );
-                        f.render_widget(input_widget, chunks[2]);
+                        f.render_widget(input_widget, chunks[3]);
 
                         // --------
-                        let x =
-                            chunks[2].x + (app.input.width() as u16 % (chunks[2].width - 2)) + 1;
-                        let y =
-                            chunks[2].y + (app.input.width() as u16 / (chunks[2].width - 2)) + 1;
-                        f.set_cursor(x, y);
+                        let cursor_x = chunks[3].x + x as u16 + 1;
+                        let cursor_y = chunks[3].y + (y - offset) as u16 + 1;
+                        if app.focus == Focus::Input {
+                            f.set_cursor(cursor_x, cursor_y);
+                        }
+
+                        // --------
+                        if let Some(completion) = &app.completion {
+                            let items: Vec<_> = completion
+                                .candidates
+                                .iter()
+                                .enumerate()
+                                .map(|(i, c)| {
+                                    let style = if i == completion.selected {
+                                        Style::default().add_modifier(Modifier::REVERSED)
+                                    } else {
+                                        Style::default()
+                                    };
+                                    ListItem::new(Span::styled(c.as_str(), style))
+                                })
+                                .collect();
+                            // show the candidates above the input, aligned with the completed word
+                            // and scrolled to the selected one
+                            let size = f.size();
+                            let height = (items.len() as u16 + 2).min(chunks[3].y).min(10);
+                            let width = completion.candidates.iter().map(|c| c.width()).max();
+                            let width = (width.unwrap_or(0) as u16 + 2).min(size.width);
+                            let selected = completion.candidates[completion.selected].width();
+                            let word_x = cursor_x.saturating_sub(selected as u16 + 1);
+                            let area = Rect::new(
+                                word_x.min(size.width - width),
+                                chunks[3].y - height,
+                                width,
+                                height,
+                            );
+                            let skip = (completion.selected + 3).saturating_sub(height as usize);
+                            let items: Vec<_> = items.into_iter().skip(skip).collect();
+                            f.render_widget(Clear, area);
+                            f.render_widget(
+                                List::new(items).block(
+                                    Block::default()
+                                        .borders(Borders::ALL)
+                                        .border_style(app.style(theme.border())),
+                                ),
+                                area,
+                            );
+                        }
+
+                        // --------
+                        if let Some(menu) = &app.menu {
+                            let bold = Style::default().add_modifier(Modifier::BOLD);
+                            let items: Vec<_> = MessageMenu::items()
+                                .into_iter()
+                                .map(|item| Span::styled(format!(" {} ", item), bold))
+                                .collect();
+                            f.render_widget(Clear, menu.area);
+                            f.render_widget(
+                                Paragraph::new(Spans::from(items)).block(
+                                    Block::default()
+                                        .title("Reply with")
+                                        .borders(Borders::ALL)
+                                        .border_style(app.style(theme.input())),
+                                ),
+                                menu.area,
+                            );
+                        }
                     })
                     .unwrap();
 
@@ -215,69 +1382,168 @@ impl super::App for TuiApp {
                 }
 
                 // receive event from other tasks
-                match event_rx.recv() {
+                let event = event_rx.recv();
+                // any other key ends the completion
+                if let Ok(AppEvent::Key(key)) = &event {
+                    if !matches!(key, Key::Char('\t') | Key::BackTab) {
+                        app.completion = None;
+                    }
+                }
+                match event {
                     // keyboard
+                    Ok(AppEvent::Key(key)) if app.search.is_some() => app.handle_search_key(key),
+                    Ok(AppEvent::Key(key)) if app.handle_menu_key(key, &input_tx) => {}
+                    Ok(AppEvent::Key(key))
+                        if app.message_search.as_ref().is_some_and(|s| s.editing) =>
+                    {
+                        app.handle_message_search_key(key, &input_tx)
+                    }
+                    Ok(AppEvent::Key(key))
+                        if app.focus == Focus::Users && app.handle_users_key(key) => {}
                     Ok(AppEvent::Key(key)) => match key {
-                        // return key
-                        Key::Char('\n') if app.input.is_empty() => {}
-                        Key::Char('\n') => {
-                            let text: String = app.input.drain(..).collect();
-                            app.last_input = text.clone();
+                        // complete, or switch the focus if there's nothing to complete
+                        Key::Char('\t') | Key::BackTab => {
+                            let backward = key == Key::BackTab;
+                            if app.focus != Focus::Input || !app.complete(backward) {
+                                app.focus = app.focus.next(backward);
+                                app.user_popup = false;
+                            }
+                        }
+                        // search the messages
+                        Key::Char('/')
+                            if app.focus == Focus::Messages
+                                || (app.focus == Focus::Input && app.input.is_empty()) =>
+                        {
+                            app.message_search = Some(MessageSearch::new(false));
+                            app.focus = Focus::Input;
+                        }
+                        Key::Char('n') if app.focus == Focus::Messages => app.jump(true),
+                        Key::Char('N') if app.focus == Focus::Messages => app.jump(false),
+                        // back to the input, ending the search
+                        Key::Esc if app.focus != Focus::Input => {
+                            app.focus = Focus::Input;
+                            app.user_popup = false;
+                            app.message_search = None;
+                        }
+                        // scroll messages by lines while focused
+                        Key::Up | Key::Char('k') if app.focus == Focus::Messages => {
+                            app.scroll_up(1)
+                        }
+                        Key::Down | Key::Char('j') if app.focus == Focus::Messages => {
+                            app.scroll_down(1)
+                        }
+                        Key::Home if app.focus == Focus::Messages => {
+                            app.scroll_up(app.buffer().messages.len())
+                        }
+                        Key::End if app.focus == Focus::Messages => {
+                            app.scroll_down(app.buffer().scroll)
+                        }
+                        Key::Up | Key::Down if app.focus == Focus::Users => {}
+                        // typing goes back to the input
+                        Key::Char(_) | Key::Backspace if app.focus != Focus::Input => {
+                            app.focus = Focus::Input;
+                            app.user_popup = false;
+                            app.input.handle_key(key);
+                        }
+                        // return key, Alt-Enter does the other way
+                        Key::Char('\n') if app.multiline => app.input.insert('\n'),
+                        Key::Alt('\r') | Key::Alt('\n') if !app.multiline => app.input.insert('\n'),
+                        Key::Char('\n') | Key::Alt('\r') | Key::Alt('\n')
+                            if app.input.is_empty() => {}
+                        Key::Char('\n') | Key::Alt('\r') | Key::Alt('\n') => {
+                            let text = app.input.take();
+                            app.history.push(text.clone());
 
-                            if text.starts_with(":") {
+                            if let Some(command) = command::parse(&text, true) {
                                 // client command
-                                match text[1..].to_lowercase().as_str() {
-                                    "exit" => {
-                                        input_tx.send(ClientInput::Exit).unwrap();
-                                        exited = true;
-                                    }
-                                    "clear" => app.messages.clear(),
-
-                                    "fuck" => app.messages.push((
-                                        "=> What's your problem?".to_string(),
-                                        Style::default().fg(Color::Red),
-                                    )),
-                                    cmd @ _ => app.messages.push((
-                                        format!("=> Invalid command `{}`", cmd),
-                                        Style::default().fg(Color::Red),
-                                    )),
+                                match command {
+                                    Ok(command) => exited = app.run_command(command, &input_tx),
+                                    Err(e) => app.push_message(Entry::error(e)),
                                 }
                             } else {
                                 // normal message
-                                input_tx.send(ClientInput::Text(text)).unwrap();
+                                input_tx.send(app.outgoing(Message::Text(text))).unwrap();
                             }
                         }
-                        // push new char
-                        Key::Char(char) if app.input.len() < 140 => {
-                            app.input.push(char);
-                        }
-                        // pop the last char
-                        Key::Backspace => {
-                            app.input.pop();
-                        }
                         // escape
                         Key::Esc => {
                             input_tx.send(ClientInput::Exit).unwrap();
                             exited = true;
                         }
-                        // fetch last input
-                        Key::Up if app.input.is_empty() => {
-                            app.input = app.last_input.clone();
+                        // browse and search the history
+                        Key::Ctrl('o') => app.multiline = !app.multiline,
+                        Key::Up if !app.input.is_multiline() => {
+                            if let Some(line) = app.history.prev(app.input.text()) {
+                                app.input.set(line.to_owned());
+                            }
+                        }
+                        Key::Down if !app.input.is_multiline() => {
+                            if let Some(line) = app.history.next() {
+                                app.input.set(line.to_owned());
+                            }
+                        }
+                        Key::Ctrl('r') => {
+                            app.search = Some(ReverseSearch::new(app.input.text().to_owned()));
+                        }
+                        // scroll messages
+                        Key::PageUp => app.scroll_up(app.page()),
+                        Key::PageDown => app.scroll_down(app.page()),
+                        Key::Home if app.input.is_empty() => {
+                            app.scroll_up(app.buffer().messages.len())
+                        }
+                        Key::Alt('m') | Key::Alt('M') => app.toggle_mentions(),
+                        Key::Alt('r') | Key::Alt('R') => app.raw_markup = !app.raw_markup,
+                        Key::End if app.input.is_empty() => app.scroll_down(app.buffer().scroll),
+                        // switch tabs
+                        Key::Alt(c @ '1'..='9') => app.switch_tab(c as usize - '1' as usize),
+                        Key::Ctrl('n') => app.switch_tab((app.current + 1) % app.tabs.len()),
+                        Key::Ctrl('p') => {
+                            app.switch_tab((app.current + app.tabs.len() - 1) % app.tabs.len())
+                        }
+                        // edit input
+                        key => {
+                            app.input.handle_key(key);
+                        }
+                    },
+                    // mouse
+                    Ok(AppEvent::Mouse(mouse)) => match mouse {
+                        MouseEvent::Press(MouseButton::WheelUp, _, _) => app.scroll_up(WHEEL_STEP),
+                        MouseEvent::Press(MouseButton::WheelDown, _, _) => {
+                            app.scroll_down(WHEEL_STEP)
+                        }
+                        // termion counts from 1
+                        MouseEvent::Press(MouseButton::Left, x, y) => {
+                            app.click(x.saturating_sub(1), y.saturating_sub(1), &input_tx);
                         }
                         _ => {}
                     },
                     // show message
                     Ok(AppEvent::Message(content)) => {
-                        app.messages.push(content);
+                        app.push_message(content);
+                    }
+                    Ok(AppEvent::UserMessage(user, message, id)) => {
+                        // all messages are in the lobby for now
+                        if app.push_user_message(Self::lobby(), Some(id), user, message) {
+                            app.notify(terminal.backend_mut());
+                        }
+                    }
+                    Ok(AppEvent::DirectMessage(from, to, message)) => {
+                        let peer = if from == app.username {
+                            to
+                        } else {
+                            from.clone()
+                        };
+                        let conversation = Conversation::Direct(peer);
+                        if app.push_user_message(conversation, None, from, message) {
+                            app.notify(terminal.backend_mut());
+                        }
                     }
                     // update user list
-                    Ok(AppEvent::UserList(users)) => {
-                        app.users = users
-                            .into_iter()
-                            .map(|(n, _a)| n)
-                            .map(|s| (s, Style::default()))
-                            .collect();
+                    Ok(AppEvent::UserList(users)) => app.update_users(users),
+                    Ok(AppEvent::Member(change, addr, time)) => {
+                        app.change_member(change, addr, time)
                     }
+                    Ok(AppEvent::SearchResults(query, results)) => app.show_results(query, results),
                     // update server name
                     Ok(AppEvent::ServerName(name)) => {
                         app.server_name = name;
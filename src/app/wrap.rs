@@ -0,0 +1,188 @@
+//! Word wrapping of styled text, aware of wide characters
+
+use tui::style::Style;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+pub type StyledString = (String, Style);
+
+/// Wrap styled `spans` into lines no wider than `width` columns, breaking at whitespace
+/// when possible and always at newlines. Lines after the first one are indented by `indent`
+/// columns, unless that leaves too little room.
+pub fn wrap(spans: &[StyledString], width: usize, indent: usize) -> Vec<Vec<StyledString>> {
+    if width == 0 {
+        return vec![spans.to_vec()];
+    }
+    let indent = if indent * 2 > width { 0 } else { indent };
+
+    let mut lines = Vec::new();
+    let mut line = Line::default();
+
+    for (text, style) in spans {
+        for token in tokens(text) {
+            if token == "\n" {
+                lines.push(line.finish());
+                line = Line::indented(indent);
+                continue;
+            }
+
+            let token_width = token.width();
+            let is_space = token.chars().all(char::is_whitespace);
+            if line.width + token_width <= width {
+                line.push(token, *style, token_width);
+            } else if is_space {
+                // break at the whitespace, which is dropped
+                lines.push(line.finish());
+                line = Line::indented(indent);
+            } else if token_width <= width - indent && line.has_content(indent) {
+                // move the whole word to the next line
+                lines.push(line.finish());
+                line = Line::indented(indent);
+                line.push(token, *style, token_width);
+            } else {
+                // the word is too long for any line, break it anywhere
+                for c in token.chars() {
+                    let char_width = c.width().unwrap_or(0);
+                    if line.width + char_width > width && line.has_content(indent) {
+                        lines.push(line.finish());
+                        line = Line::indented(indent);
+                    }
+                    line.push(&c.to_string(), *style, char_width);
+                }
+            }
+        }
+    }
+    lines.push(line.finish());
+
+    lines
+}
+
+/// Split text into runs of whitespace, runs of non-whitespace, and single newlines
+fn tokens(text: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut start = 0;
+    let mut last_space = None;
+
+    for (i, c) in text.char_indices() {
+        if c == '\n' {
+            if start < i {
+                tokens.push(&text[start..i]);
+            }
+            tokens.push("\n");
+            start = i + 1;
+            last_space = None;
+            continue;
+        }
+        let is_space = c.is_whitespace();
+        if last_space.is_some_and(|last| last != is_space) && start < i {
+            tokens.push(&text[start..i]);
+            start = i;
+        }
+        last_space = Some(is_space);
+    }
+    if start < text.len() {
+        tokens.push(&text[start..]);
+    }
+
+    tokens
+}
+
+/// A line being built
+#[derive(Default)]
+struct Line {
+    spans: Vec<StyledString>,
+    width: usize,
+}
+
+impl Line {
+    fn indented(indent: usize) -> Self {
+        let mut line = Self::default();
+        if indent > 0 {
+            line.push(&" ".repeat(indent), Style::default(), indent);
+        }
+        line
+    }
+
+    fn has_content(&self, indent: usize) -> bool {
+        self.width > indent
+    }
+
+    /// Append text, merging it into the last span if the style is the same
+    fn push(&mut self, text: &str, style: Style, width: usize) {
+        match self.spans.last_mut() {
+            Some((last, last_style)) if *last_style == style => last.push_str(text),
+            _ => self.spans.push((text.to_owned(), style)),
+        }
+        self.width += width;
+    }
+
+    fn finish(self) -> Vec<StyledString> {
+        self.spans
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tui::style::Modifier;
+
+    /// Wrap unstyled text and return the text of each line
+    fn wrap_text(text: &str, width: usize, indent: usize) -> Vec<String> {
+        let lines = wrap(&[(text.to_owned(), Style::default())], width, indent);
+        (lines.iter())
+            .map(|line| line.iter().map(|(text, _)| text.as_str()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn breaks_at_whitespace() {
+        assert_eq!(wrap_text("hello world foo", 11, 0), ["hello world", "foo"]);
+        // whitespace is kept while it fits, and dropped where the line breaks
+        assert_eq!(wrap_text("hello world", 8, 0), ["hello ", "world"]);
+        assert_eq!(wrap_text("hello world", 5, 0), ["hello", "world"]);
+        assert_eq!(wrap_text("aaa bbb ccc", 7, 2), ["aaa bbb", "  ccc"]);
+    }
+
+    #[test]
+    fn breaks_at_newlines() {
+        assert_eq!(wrap_text("a\nb", 10, 0), ["a", "b"]);
+        assert_eq!(wrap_text("a\n\nb", 10, 2), ["a", "  ", "  b"]);
+    }
+
+    #[test]
+    fn long_words_are_split() {
+        assert_eq!(wrap_text("abcdefgh", 3, 0), ["abc", "def", "gh"]);
+        // too little room left by the indent, so none is used
+        assert_eq!(wrap_text("abcdefgh", 3, 2), ["abc", "def", "gh"]);
+    }
+
+    #[test]
+    fn wide_characters() {
+        assert_eq!(wrap_text("你好世界", 5, 0), ["你好", "世界"]);
+        assert_eq!(wrap_text("你好 世界", 4, 0), ["你好", "世界"]);
+        // a character wider than the line still goes somewhere
+        assert_eq!(wrap_text("你好", 1, 0), ["你", "好"]);
+    }
+
+    #[test]
+    fn emoji() {
+        assert_eq!(wrap_text("👍👍👍", 4, 0), ["👍👍", "👍"]);
+        assert_eq!(wrap_text("ok 👍", 4, 0), ["ok ", "👍"]);
+    }
+
+    #[test]
+    fn styles_are_kept() {
+        let bold = Style::default().add_modifier(Modifier::BOLD);
+        let spans = [
+            ("ab ".to_owned(), bold),
+            ("cd".to_owned(), Style::default()),
+        ];
+        assert_eq!(
+            wrap(&spans, 3, 0),
+            [
+                vec![("ab ".to_owned(), bold)],
+                vec![("cd".to_owned(), Style::default())]
+            ]
+        );
+        assert_eq!(wrap(&spans, 0, 0), [spans.to_vec()]);
+    }
+}
@@ -5,86 +5,155 @@ use futures::{
     stream::{SplitSink, SplitStream},
     StreamExt,
 };
+use std::time::Duration;
 use tokio::{net::TcpStream, sync::mpsc};
-use tokio_util::codec::{Framed, LinesCodec};
+use tokio_util::codec::Framed;
 
-use crate::app::{App, BasicApp, TuiApp};
+use crate::app::{App, BasicApp, Config, HeadlessApp, TuiApp};
+use crate::codec::{ChatCodec, Encoding};
 use crate::message::*;
 use crate::protocol::*;
 
-type Transport = Framed<TcpStream, LinesCodec>;
-type Tx = SplitSink<Transport, String>;
+type Transport = Framed<TcpStream, ChatCodec<ServerCommand>>;
+type Tx = SplitSink<Transport, ClientCommand>;
 type Rx = SplitStream<Transport>;
 
+/// How long to wait for the server to close the connection on exit
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
 /// The chat client
 pub struct Client {
     name: String,
     server: String,
     port: u16,
     tui: bool,
+    encoding: Encoding,
+    config: Config,
 }
 
 /// Types of input from the app
 #[derive(Debug)]
 pub enum ClientInput {
-    Text(String),
+    Message(Message),
+    /// A direct message to a user
+    Direct(User, Message),
+    /// Change our name
+    SetName(String),
+    /// Search the messages of a room, or all of them, on the server
+    Search {
+        query: String,
+        room: Option<String>,
+        author: Option<User>,
+    },
     Exit,
+    /// Stop with an error, e.g. why a headless run failed
+    Fail(Error),
 }
 
 impl Client {
-    pub fn new(name: &str, server: &str, port: u16, tui: bool) -> Self {
-        let client = Self {
+    pub fn new(
+        name: &str,
+        server: &str,
+        port: u16,
+        tui: bool,
+        encoding: Encoding,
+        config: Config,
+    ) -> Self {
+        Self {
             name: name.to_owned(),
             server: server.to_owned(),
             port,
             tui,
-        };
-        client
+            encoding,
+            config,
+        }
     }
 
-    /// Connect to server and then send/receive messages
+    /// Connect to server and then send/receive messages, failing on the timeout of a headless run
     pub async fn run(&self) -> Result<()> {
+        match self.config.headless.as_ref().and_then(|h| h.timeout) {
+            Some(timeout) => {
+                (tokio::time::timeout(timeout, self.session()).await).unwrap_or(Err(Error::Timeout))
+            }
+            None => self.session().await,
+        }
+    }
+
+    async fn session(&self) -> Result<()> {
         let server = (self.server.to_owned(), self.port);
-        println!("Connecting to {:?}...", server);
+        // keep the output of headless runs for the messages
+        if self.config.headless.is_none() {
+            println!("Connecting to {:?}...", server);
+        }
         let stream = TcpStream::connect(server).await?;
-        let (mut tcp_tx, mut tcp_rx) = Framed::new(stream, LinesCodec::new()) // split tcp stream data into framed line's
-            .split::<String>(); // split the framed stream into two halves
+        let mut transport: Transport = Framed::new(stream, ChatCodec::new()); // frame and decode tcp stream data
+
+        // negotiate the encoding before anything else, switching once the server acknowledges it,
+        // the commands received before being kept for the app
+        let mut early = Vec::new();
+        if self.encoding != Encoding::default() {
+            transport
+                .send(ClientCommand::SetEncoding(self.encoding))
+                .await?;
+            while let Some(command) = transport.next().await {
+                match command? {
+                    ServerCommand::Encoding(encoding) => {
+                        transport.codec_mut().set_encoding(encoding);
+                        break;
+                    }
+                    // not supported by the server, so JSON is kept, unless a script asked for it
+                    ServerCommand::Error(_) if self.config.headless.is_some() => {
+                        return Err(Error::UnsupportedEncoding(self.encoding));
+                    }
+                    ServerCommand::Error(e) => {
+                        early.push(ServerCommand::Error(e));
+                        break;
+                    }
+                    command => early.push(command),
+                }
+            }
+        }
+        let (mut tcp_tx, mut tcp_rx) = transport.split::<ClientCommand>(); // split the framed stream into two halves
 
         // the following channels are used to communicate between the client and the app
         let (msg_tx, msg_rx) = mpsc::unbounded_channel::<ServerCommand>();
         let (input_tx, mut input_rx) = mpsc::unbounded_channel::<ClientInput>();
 
+        for command in early {
+            let _ = msg_tx.send(command);
+        }
+
         // launch the app task
         if self.tui {
-            TuiApp::start(input_tx, msg_rx, &self.name)?;
+            TuiApp::start(input_tx, msg_rx, &self.name, self.config.clone())?;
+        } else if self.config.headless.is_some() {
+            HeadlessApp::start(input_tx, msg_rx, &self.name, self.config.clone())?;
         } else {
-            BasicApp::start(input_tx, msg_rx, &self.name)?;
+            BasicApp::start(input_tx, msg_rx, &self.name, self.config.clone())?;
         }
 
         // recv task: read from `tcp_rx`, send to `msg_tx`
-        let _recv_task = tokio::spawn(async move {
+        let recv_task = tokio::spawn(async move {
             while let Some(result) = tcp_rx.next().await {
                 match result {
-                    Ok(raw_str) => {
-                        if let Ok(command) = serde_json::from_str::<ServerCommand>(&raw_str) {
-                            // deserialized into ServerCommand
-                            let _ = msg_tx.send(command);
-                        } else {
-                            let _ = msg_tx.send(ServerCommand::Error(raw_str));
-                        }
+                    Ok(command) => {
+                        let _ = msg_tx.send(command);
                     }
-                    Err(_) => {
+                    Err(e) if e.is_fatal() => {
                         break;
                     }
+                    Err(e) => {
+                        let _ = msg_tx.send(ServerCommand::Error(e.to_string()));
+                    }
                 }
             }
         });
 
         // send task: read from `input_rx`, send to `tcp_tx`
-        let _send_task = {
+        {
             macro_rules! send {
                 ($msg:expr) => {
-                    let _ = tcp_tx.send(serde_json::to_string(&$msg).unwrap()).await;
+                    let _ = tcp_tx.send($msg).await;
                 };
             }
 
@@ -93,16 +162,44 @@ impl Client {
 
             while let Some(input) = input_rx.next().await {
                 match input {
-                    ClientInput::Text(text) => {
+                    ClientInput::Message(message) => {
                         // read messages from input_rx(app) and send them
-                        send!(ClientCommand::SendMessage(Message::Text(text)));
+                        send!(ClientCommand::SendMessage(message));
+                    }
+                    ClientInput::Direct(user, message) => {
+                        send!(ClientCommand::SendDirect(user, message));
+                    }
+                    ClientInput::SetName(name) => {
+                        send!(ClientCommand::SetName(name));
+                    }
+                    ClientInput::Search {
+                        query,
+                        room,
+                        author,
+                    } => {
+                        send!(ClientCommand::Search {
+                            query,
+                            room,
+                            author,
+                            since: None,
+                            until: None,
+                            limit: None,
+                        });
                     }
                     ClientInput::Exit => {
                         break;
                     }
+                    ClientInput::Fail(e) => {
+                        return Err(e);
+                    }
                 }
             }
-        };
+        }
+
+        // close our half then let the server close its own, so that it handles all we sent
+        // instead of the connection being reset by unread data, e.g. after a piped input
+        let _ = tcp_tx.close().await;
+        let _ = tokio::time::timeout(CLOSE_TIMEOUT, recv_task).await;
 
         Ok(())
     }
@@ -0,0 +1,278 @@
+use std::{io, marker::PhantomData, str::FromStr};
+
+use bytes::{Bytes, BytesMut};
+use serde::{de::DeserializeOwned, Serialize};
+use tokio_util::codec::{Decoder, Encoder, LengthDelimitedCodec, LinesCodec, LinesCodecError};
+
+use crate::error::*;
+
+/// Maximum size of a single frame on the wire, for both line and length-delimited framing
+pub const MAX_FRAME_LENGTH: usize = 256 * 1024;
+
+/// Wire encodings a connection may use
+///
+/// Every connection starts with `Json`, and the client may switch to another one by sending
+/// `ClientCommand::SetEncoding` as its first command, then waiting for `ServerCommand::Encoding`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Encoding {
+    /// JSON, one command per line
+    #[default]
+    Json,
+    /// MessagePack, each command prefixed with its length
+    MessagePack,
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(Encoding::Json),
+            "msgpack" | "messagepack" => Ok(Encoding::MessagePack),
+            _ => Err(format!("unknown encoding `{}`", s)),
+        }
+    }
+}
+
+/// Frames and (de)serializes commands, decoding into `D` and encoding anything serializable
+///
+/// Both framings are kept so that the encoding can be switched in place with `set_encoding`
+/// without losing data already buffered by `Framed`.
+pub struct ChatCodec<D> {
+    encoding: Encoding,
+    lines: LinesCodec,
+    length_delimited: LengthDelimitedCodec,
+    _marker: PhantomData<fn() -> D>,
+}
+
+impl<D> ChatCodec<D> {
+    pub fn new() -> Self {
+        Self {
+            encoding: Encoding::Json,
+            lines: LinesCodec::new_with_max_length(MAX_FRAME_LENGTH),
+            length_delimited: LengthDelimitedCodec::builder()
+                .max_frame_length(MAX_FRAME_LENGTH)
+                .new_codec(),
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    /// Switch the encoding of all following frames, in both directions
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        self.encoding = encoding;
+    }
+}
+
+impl<D> Default for ChatCodec<D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<D: DeserializeOwned> ChatCodec<D> {
+    fn decode_frame(&mut self, frame: Option<FrameBuf>) -> Result<Option<D>> {
+        Ok(match frame {
+            Some(FrameBuf::Line(line)) => Some(serde_json::from_str(&line)?),
+            Some(FrameBuf::Bytes(bytes)) => Some(rmp_serde::from_slice(&bytes)?),
+            None => None,
+        })
+    }
+}
+
+/// A raw frame split out by one of the framings
+enum FrameBuf {
+    Line(String),
+    Bytes(BytesMut),
+}
+
+impl<D: DeserializeOwned> Decoder for ChatCodec<D> {
+    type Item = D;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<D>> {
+        let frame = match self.encoding {
+            Encoding::Json => self
+                .lines
+                .decode(src)
+                .map_err(lines_error)?
+                .map(FrameBuf::Line),
+            Encoding::MessagePack => self
+                .length_delimited
+                .decode(src)
+                .map_err(length_delimited_error)?
+                .map(FrameBuf::Bytes),
+        };
+        self.decode_frame(frame)
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<D>> {
+        let frame = match self.encoding {
+            Encoding::Json => self
+                .lines
+                .decode_eof(src)
+                .map_err(lines_error)?
+                .map(FrameBuf::Line),
+            Encoding::MessagePack => self
+                .length_delimited
+                .decode_eof(src)
+                .map_err(length_delimited_error)?
+                .map(FrameBuf::Bytes),
+        };
+        self.decode_frame(frame)
+    }
+}
+
+impl<D, T: Serialize> Encoder<T> for ChatCodec<D> {
+    type Error = Error;
+
+    fn encode(&mut self, item: T, dst: &mut BytesMut) -> Result<()> {
+        match self.encoding {
+            Encoding::Json => {
+                let line = serde_json::to_string(&item)?;
+                if line.len() > MAX_FRAME_LENGTH {
+                    return Err(Error::FrameTooLarge);
+                }
+                self.lines.encode(line, dst).map_err(lines_error)
+            }
+            Encoding::MessagePack => {
+                let bytes = rmp_serde::to_vec(&item)?;
+                self.length_delimited
+                    .encode(Bytes::from(bytes), dst)
+                    .map_err(length_delimited_error)
+            }
+        }
+    }
+}
+
+/// Whether `item` fits in a frame of any encoding, json being the largest one
+pub fn fits_in_frame<T: Serialize>(item: &T) -> bool {
+    serde_json::to_string(item).is_ok_and(|line| line.len() <= MAX_FRAME_LENGTH)
+}
+
+pub(crate) fn lines_error(e: LinesCodecError) -> Error {
+    match e {
+        LinesCodecError::MaxLineLengthExceeded => Error::FrameTooLarge,
+        LinesCodecError::Io(e) => e.into(),
+    }
+}
+
+/// `LengthDelimitedCodec` reports oversized frames as `InvalidData` / `InvalidInput` io errors
+fn length_delimited_error(e: io::Error) -> Error {
+    match e.kind() {
+        io::ErrorKind::InvalidData | io::ErrorKind::InvalidInput => Error::FrameTooLarge,
+        _ => e.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Message;
+    use crate::protocol::ClientCommand;
+
+    fn text(command: Option<ClientCommand>) -> Option<String> {
+        match command {
+            Some(ClientCommand::SendMessage(Message::Text(text))) => Some(text),
+            _ => None,
+        }
+    }
+
+    fn round_trip(encoding: Encoding) {
+        let mut codec = ChatCodec::<ClientCommand>::new();
+        codec.set_encoding(encoding);
+        let mut buf = BytesMut::new();
+        let command = ClientCommand::SendMessage(Message::Text("héllo\nwörld".to_owned()));
+        codec.encode(command, &mut buf).unwrap();
+        assert_eq!(
+            text(codec.decode(&mut buf).unwrap()).as_deref(),
+            Some("héllo\nwörld")
+        );
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn round_trip_json() {
+        round_trip(Encoding::Json);
+    }
+
+    #[test]
+    fn round_trip_msgpack() {
+        round_trip(Encoding::MessagePack);
+    }
+
+    #[test]
+    fn partial_frames_wait_for_more() {
+        for encoding in [Encoding::Json, Encoding::MessagePack] {
+            let mut codec = ChatCodec::<ClientCommand>::new();
+            codec.set_encoding(encoding);
+            let mut buf = BytesMut::new();
+            let command = ClientCommand::SendMessage(Message::Text("hi".to_owned()));
+            codec.encode(command, &mut buf).unwrap();
+            let mut rest = buf.split_off(buf.len() / 2);
+            assert!(codec.decode(&mut buf).unwrap().is_none());
+            buf.unsplit(rest.split());
+            assert_eq!(text(codec.decode(&mut buf).unwrap()).as_deref(), Some("hi"));
+        }
+    }
+
+    #[test]
+    fn switch_encoding_with_buffered_frames() {
+        let mut codec = ChatCodec::<ClientCommand>::new();
+        let mut buf = BytesMut::new();
+        codec
+            .encode(ClientCommand::SetEncoding(Encoding::MessagePack), &mut buf)
+            .unwrap();
+        codec.set_encoding(Encoding::MessagePack);
+        let command = ClientCommand::SendMessage(Message::Text("packed".to_owned()));
+        codec.encode(command, &mut buf).unwrap();
+
+        codec.set_encoding(Encoding::Json);
+        assert!(matches!(
+            codec.decode(&mut buf).unwrap(),
+            Some(ClientCommand::SetEncoding(Encoding::MessagePack))
+        ));
+        codec.set_encoding(Encoding::MessagePack);
+        assert_eq!(
+            text(codec.decode(&mut buf).unwrap()).as_deref(),
+            Some("packed")
+        );
+    }
+
+    #[test]
+    fn frames_are_capped() {
+        let huge = "x".repeat(MAX_FRAME_LENGTH + 1);
+        for encoding in [Encoding::Json, Encoding::MessagePack] {
+            let mut codec = ChatCodec::<ClientCommand>::new();
+            codec.set_encoding(encoding);
+            let command = ClientCommand::SendMessage(Message::Text(huge.clone()));
+            let result = codec.encode(command, &mut BytesMut::new());
+            assert!(
+                matches!(result, Err(Error::FrameTooLarge)),
+                "{:?}",
+                encoding
+            );
+        }
+
+        // a line too long is rejected before its end arrives
+        let mut codec = ChatCodec::<ClientCommand>::new();
+        let mut buf = BytesMut::from(huge.as_bytes());
+        assert!(matches!(codec.decode(&mut buf), Err(Error::FrameTooLarge)));
+
+        // so is a length prefix over the cap
+        let mut codec = ChatCodec::<ClientCommand>::new();
+        codec.set_encoding(Encoding::MessagePack);
+        let mut buf = BytesMut::from(&((MAX_FRAME_LENGTH + 1) as u32).to_be_bytes()[..]);
+        assert!(matches!(codec.decode(&mut buf), Err(Error::FrameTooLarge)));
+    }
+
+    #[test]
+    fn parse_encoding() {
+        assert_eq!("JSON".parse(), Ok(Encoding::Json));
+        assert_eq!("msgpack".parse(), Ok(Encoding::MessagePack));
+        assert!("xml".parse::<Encoding>().is_err());
+    }
+}
@@ -3,11 +3,53 @@ use thiserror::Error;
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Error, Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
     #[error("network error: {0}")]
     NetworkError(#[from] std::io::Error),
     #[error("serde error: {0}")]
     SerdeError(#[from] serde_json::error::Error),
-    #[error("codec error: {0}")]
-    CodecError(#[from] tokio_util::codec::LinesCodecError),
+    #[error("msgpack encode error: {0}")]
+    MsgPackEncodeError(#[from] rmp_serde::encode::Error),
+    #[error("msgpack decode error: {0}")]
+    MsgPackDecodeError(#[from] rmp_serde::decode::Error),
+    #[error("websocket error: {0}")]
+    WebSocketError(#[from] tokio_tungstenite::tungstenite::Error),
+    #[error("encoding {0:?} is not supported by this connection")]
+    UnsupportedEncoding(crate::codec::Encoding),
+    #[error("frame exceeds {} bytes", crate::codec::MAX_FRAME_LENGTH)]
+    FrameTooLarge,
+    #[error("config error: {0}")]
+    ConfigError(String),
+    #[error("connection closed by the server")]
+    Disconnected,
+    #[error("name rejected: {0}")]
+    NameRejected(String),
+    #[error("timed out")]
+    Timeout,
+    #[error("link refused by the remote: {0}")]
+    LinkRefused(String),
+}
+
+impl Error {
+    /// Whether the connection cannot be used any more after this error
+    pub fn is_fatal(&self) -> bool {
+        matches!(
+            self,
+            Error::NetworkError(_) | Error::WebSocketError(_) | Error::FrameTooLarge
+        )
+    }
+
+    /// Exit code of the client failing with this error, so that scripts can tell why
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::NetworkError(_)
+            | Error::WebSocketError(_)
+            | Error::Disconnected
+            | Error::UnsupportedEncoding(_) => 2,
+            Error::NameRejected(_) => 3,
+            Error::Timeout => 4,
+            _ => 1,
+        }
+    }
 }
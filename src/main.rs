@@ -5,12 +5,17 @@ extern crate serde;
 
 mod app;
 mod client;
+mod codec;
 mod error;
 mod message;
 mod protocol;
 mod server;
 mod utils;
 
+use std::{path::PathBuf, time::Duration};
+
+use crate::app::{Config, Headless, Membership, Notify, Palette, Themes};
+use crate::codec::Encoding;
 use crate::error::*;
 use structopt::StructOpt;
 
@@ -29,38 +34,160 @@ pub enum Opt {
         name: String,
         #[structopt(short, long)]
         basic: bool,
+        /// Wire encoding: json or msgpack
+        #[structopt(short, long, default_value = "json")]
+        encoding: Encoding,
+        /// A word that counts as a mention besides @name, can be repeated
+        #[structopt(short, long)]
+        keyword: Vec<String>,
+        /// How to notify mentions: none, bell or osc (desktop notification)
+        #[structopt(long, default_value = "bell")]
+        notify: Notify,
+        /// How to tell users joining and leaving: show, collapse (consecutive ones) or hide
+        #[structopt(long, default_value = "show")]
+        membership: Membership,
+        /// Colors of user names: default or colorblind
+        #[structopt(long, default_value = "default")]
+        palette: Palette,
+        /// Theme and layout config file [default: <config dir>/chat/config.toml]
+        #[structopt(long, parse(from_os_str))]
+        config: Option<PathBuf>,
+        /// Send the lines of stdin without interaction, then exit: 2 on connection errors, 3 if
+        /// the name is rejected, 4 on timeout
+        #[structopt(long)]
+        headless: bool,
+        /// Send this message instead of the lines of stdin, implies --headless
+        #[structopt(short, long)]
+        message: Option<String>,
+        /// Wait for this many messages of other users before exiting, implies --headless
+        #[structopt(long, default_value = "0")]
+        wait: usize,
+        /// Fail if not done after this many seconds, implies --headless
+        #[structopt(long)]
+        timeout: Option<u64>,
     },
     Server {
         #[structopt(short, long, default_value = "30388")]
         port: u16,
         #[structopt(short, long, default_value = "")]
         name: String,
+        /// Also accept websocket clients on this port
+        #[structopt(long)]
+        ws_port: Option<u16>,
+        /// Also serve the http administration api on this port
+        #[structopt(long, requires = "http-token")]
+        http_port: Option<u16>,
+        /// Bearer token required by the http api
+        #[structopt(long, env = "CHAT_HTTP_TOKEN", hide_env_values = true)]
+        http_token: Option<String>,
+        /// Also accept IRC clients on this port
+        #[structopt(long)]
+        irc_port: Option<u16>,
+        /// Link to another server at host:port and relay messages with it, can be repeated
+        #[structopt(long, requires = "link-secret")]
+        link: Vec<String>,
+        /// Secret shared by linked servers, links from other servers are refused without it
+        #[structopt(long, env = "CHAT_LINK_SECRET", hide_env_values = true)]
+        link_secret: Option<String>,
+        /// Load the message history from this file, and save new messages to it
+        #[structopt(long, parse(from_os_str))]
+        history: Option<PathBuf>,
     },
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() {
     env_logger::builder()
         .filter_level(log::LevelFilter::Info)
         .init();
 
-    match Opt::from_args() {
+    if let Err(e) = run(Opt::from_args()).await {
+        eprintln!("Error: {}", e);
+        std::process::exit(e.exit_code());
+    }
+    std::process::exit(0);
+}
+
+async fn run(opt: Opt) -> Result<()> {
+    match opt {
         Opt::Client {
             server,
             port,
             name,
             basic: raw,
+            encoding,
+            keyword,
+            notify,
+            membership,
+            palette,
+            config,
+            headless,
+            message,
+            wait,
+            timeout,
         } => {
             let name = utils::new_name(name);
-            let client = client::Client::new(&name, &server, port, !raw);
+            let headless =
+                (headless || message.is_some() || wait > 0 || timeout.is_some()).then(|| {
+                    Headless {
+                        message,
+                        replies: wait,
+                        timeout: timeout.map(Duration::from_secs),
+                    }
+                });
+            let tui = !raw && headless.is_none();
+            // only the tui has themes, so don't fail other apps on a bad config file
+            let config_path = config
+                .or_else(|| dirs::config_dir().map(|dir| dir.join("chat").join("config.toml")));
+            let themes = match config_path {
+                Some(path) if tui => Themes::load(&path)?,
+                _ => Themes::default(),
+            };
+            let config = Config {
+                keywords: keyword,
+                notify,
+                membership,
+                palette,
+                no_color: std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty()),
+                themes,
+                headless,
+            };
+            let client = client::Client::new(&name, &server, port, tui, encoding, config);
             client.run().await?;
         }
-        Opt::Server { port, name } => {
+        Opt::Server {
+            port,
+            name,
+            ws_port,
+            http_port,
+            http_token,
+            irc_port,
+            link,
+            link_secret,
+            history,
+        } => {
             let name = utils::new_name(name);
-            let server = server::Server::new(port, name).await?;
+            let mut server = server::Server::new(port, name).await?;
+            if let Some(history) = history {
+                server.history_file(&history).await?;
+            }
+            if let Some(ws_port) = ws_port {
+                server.listen_websocket(ws_port).await?;
+            }
+            if let (Some(http_port), Some(http_token)) = (http_port, http_token) {
+                server.listen_http(http_port, http_token).await?;
+            }
+            if let Some(irc_port) = irc_port {
+                server.listen_irc(irc_port).await?;
+            }
+            if let Some(secret) = link_secret {
+                server.link_secret(secret).await?;
+            }
+            for remote in link {
+                server.link(remote);
+            }
             server.run().await?;
         }
     }
-
-    std::process::exit(0);
+    Ok(())
 }
@@ -1,18 +1,52 @@
 use std::fmt;
 
+mod markup;
+
+pub use markup::{parse_markup, Markup, Segment};
+
 pub type User = String;
 /// All possible kinds of normal messages
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum Message {
     Text(String),
+    /// An emote like `:me waves`, told after the name of its sender
+    Action(String),
+    /// A text not to be answered automatically, e.g. from a bot
+    Notice(String),
+    /// An event told by the server, e.g. a user joining, which users can't send
+    System(String),
+}
+
+impl Message {
+    /// Name of the kind, e.g. `action`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Message::Text(_) => "text",
+            Message::Action(_) => "action",
+            Message::Notice(_) => "notice",
+            Message::System(_) => "system",
+        }
+    }
+
+    /// The text of the message, keeping the indentation and internal newlines, e.g. of code snippets
+    pub fn text(&self) -> &str {
+        let text = match self {
+            Message::Text(text)
+            | Message::Action(text)
+            | Message::Notice(text)
+            | Message::System(text) => text,
+        };
+        text.trim_start_matches(&['\r', '\n'][..]).trim_end()
+    }
 }
 
 impl fmt::Display for Message {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Message::Text(text) => {
-                write!(f, "{}", text.trim())
-            }
+            Message::Text(_) => write!(f, "{}", self.text()),
+            Message::Action(_) => write!(f, "* {}", self.text()),
+            Message::Notice(_) => write!(f, "Notice: {}", self.text()),
+            Message::System(_) => write!(f, "*** {}", self.text()),
         }
     }
 }
@@ -0,0 +1,261 @@
+//! A markdown-lite syntax for message texts
+//!
+//! - `*bold*` and `_italic_`, delimiting words within a line
+//! - `` `code` `` within a line, and code blocks fenced by lines of ```` ``` ````
+//! - `http://` and `https://` urls
+
+/// Styles of a piece of text
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Markup {
+    pub bold: bool,
+    pub italic: bool,
+    pub code: bool,
+    pub url: bool,
+}
+
+/// A piece of text with the same styles, the markup characters removed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Segment {
+    pub text: String,
+    pub markup: Markup,
+}
+
+const FENCE: &str = "```";
+
+/// Parse `text` into segments, unmatched markup characters being kept as they are
+pub fn parse_markup(text: &str) -> Vec<Segment> {
+    let mut segments = Vec::new();
+    let mut in_block = false;
+
+    let mut first = true;
+
+    for line in text.split('\n') {
+        // lines of fences are dropped, e.g. with the language name of the block
+        if line.trim_start().starts_with(FENCE) {
+            in_block = !in_block;
+            continue;
+        }
+        let markup = Markup {
+            code: in_block,
+            ..Markup::default()
+        };
+        if !first {
+            push(&mut segments, "\n", markup);
+        }
+        first = false;
+
+        if in_block {
+            push(&mut segments, line, markup);
+        } else {
+            parse_line(&mut segments, line);
+        }
+    }
+
+    segments
+}
+
+fn parse_line(segments: &mut Vec<Segment>, line: &str) {
+    let mut markup = Markup::default();
+    let mut i = 0;
+
+    while i < line.len() {
+        let rest = &line[i..];
+        let c = rest.chars().next().unwrap();
+        let at_word_start = is_boundary(line[..i].chars().next_back());
+
+        // inline code, where nothing else is parsed
+        if c == '`' {
+            if let Some(end) = rest[1..].find('`') {
+                let code = Markup {
+                    code: true,
+                    ..markup
+                };
+                push(segments, &rest[1..end + 1], code);
+                i += end + 2;
+                continue;
+            }
+        }
+
+        if at_word_start && (rest.starts_with("http://") || rest.starts_with("https://")) {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            // trailing punctuation most likely ends the sentence
+            let url = rest[..end].trim_end_matches(&['.', ',', ';', ':', '!', '?', ')'][..]);
+            let url_markup = Markup {
+                url: true,
+                ..markup
+            };
+            push(segments, url, url_markup);
+            i += url.len();
+            continue;
+        }
+
+        if c == '*' || c == '_' {
+            let open = if c == '*' { markup.bold } else { markup.italic };
+            let toggle = if open {
+                is_closing(line, i)
+            } else {
+                is_opening(line, i)
+                    && (line[i + 1..].match_indices(c)).any(|(j, _)| is_closing(line, i + 1 + j))
+            };
+            if toggle {
+                if c == '*' {
+                    markup.bold = !markup.bold;
+                } else {
+                    markup.italic = !markup.italic;
+                }
+                i += 1;
+                continue;
+            }
+        }
+
+        push(segments, &rest[..c.len_utf8()], markup);
+        i += c.len_utf8();
+    }
+}
+
+/// A delimiter at `i` opens if it's at the start of a word
+fn is_opening(line: &str, i: usize) -> bool {
+    is_boundary(line[..i].chars().next_back())
+        && line[i + 1..]
+            .chars()
+            .next()
+            .is_some_and(|c| !c.is_whitespace())
+}
+
+/// A delimiter at `i` closes if it's at the end of a word
+fn is_closing(line: &str, i: usize) -> bool {
+    is_boundary(line[i + 1..].chars().next())
+        && line[..i]
+            .chars()
+            .next_back()
+            .is_some_and(|c| !c.is_whitespace())
+}
+
+fn is_boundary(c: Option<char>) -> bool {
+    c.is_none_or(|c| !c.is_alphanumeric())
+}
+
+/// Append text, merging it into the last segment if the styles are the same
+fn push(segments: &mut Vec<Segment>, text: &str, markup: Markup) {
+    if text.is_empty() {
+        return;
+    }
+    match segments.last_mut() {
+        Some(last) if last.markup == markup => last.text.push_str(text),
+        _ => segments.push(Segment {
+            text: text.to_owned(),
+            markup,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn styled(text: &str, markup: Markup) -> Segment {
+        Segment {
+            text: text.to_owned(),
+            markup,
+        }
+    }
+
+    fn plain(text: &str) -> Segment {
+        styled(text, Markup::default())
+    }
+
+    const BOLD: Markup = Markup {
+        bold: true,
+        italic: false,
+        code: false,
+        url: false,
+    };
+    const ITALIC: Markup = Markup {
+        bold: false,
+        italic: true,
+        code: false,
+        url: false,
+    };
+
+    #[test]
+    fn emphasis() {
+        assert_eq!(
+            parse_markup("a *bold* and _italic_"),
+            vec![
+                plain("a "),
+                styled("bold", BOLD),
+                plain(" and "),
+                styled("italic", ITALIC),
+            ]
+        );
+    }
+
+    #[test]
+    fn non_ascii_emphasis() {
+        assert_eq!(parse_markup("_é"), vec![plain("_é")]);
+        assert_eq!(parse_markup("_café_"), vec![styled("café", ITALIC)]);
+        assert_eq!(parse_markup("*日本*"), vec![styled("日本", BOLD)]);
+        assert_eq!(
+            parse_markup("`é` *ü"),
+            vec![
+                styled(
+                    "é",
+                    Markup {
+                        code: true,
+                        ..Markup::default()
+                    }
+                ),
+                plain(" *ü"),
+            ]
+        );
+    }
+
+    #[test]
+    fn unclosed_markers_are_kept() {
+        assert_eq!(parse_markup("*bold"), vec![plain("*bold")]);
+        assert_eq!(parse_markup("2 * 3 = 6"), vec![plain("2 * 3 = 6")]);
+        assert_eq!(
+            parse_markup("snake_case_name"),
+            vec![plain("snake_case_name")]
+        );
+        assert_eq!(parse_markup("`code"), vec![plain("`code")]);
+    }
+
+    #[test]
+    fn nested_markers() {
+        let both = Markup {
+            bold: true,
+            italic: true,
+            ..Markup::default()
+        };
+        assert_eq!(parse_markup("*_both_*"), vec![styled("both", both)]);
+        assert_eq!(
+            parse_markup("*a _b_ c*"),
+            vec![styled("a ", BOLD), styled("b", both), styled(" c", BOLD),]
+        );
+    }
+
+    #[test]
+    fn code_blocks_and_urls() {
+        let code = Markup {
+            code: true,
+            ..Markup::default()
+        };
+        let url = Markup {
+            url: true,
+            ..Markup::default()
+        };
+        assert_eq!(
+            parse_markup("```rust\nlet *a* = 1;\n```"),
+            vec![styled("let *a* = 1;", code)]
+        );
+        assert_eq!(
+            parse_markup("see https://example.com."),
+            vec![
+                plain("see "),
+                styled("https://example.com", url),
+                plain(".")
+            ]
+        );
+    }
+}
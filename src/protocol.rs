@@ -1,3 +1,4 @@
+use crate::codec::Encoding;
 use crate::message::*;
 
 use serde::{Deserialize, Serialize};
@@ -7,27 +8,110 @@ use serde::{Deserialize, Serialize};
 pub enum ClientCommand {
     SetName(String),
     SendMessage(Message),
+    /// Send a message to the named user only
+    SendDirect(User, Message),
+    /// Search the history for messages containing all the words of `query`,
+    /// `since` and `until` being unix timestamps in seconds
+    Search {
+        query: String,
+        room: Option<String>,
+        author: Option<User>,
+        since: Option<i64>,
+        until: Option<i64>,
+        limit: Option<usize>,
+    },
+    /// Switch the wire encoding of this connection, only valid as the very first command.
+    /// The client switches once the server acknowledges it with `ServerCommand::Encoding`.
+    SetEncoding(Encoding),
+    /// Sent by another server with its name and the secret shared by linked servers,
+    /// to link with this server
+    Link {
+        server: String,
+        secret: String,
+    },
+    /// A message relayed from a linked server
+    Relay(Relay),
 }
 
 /// Command from server to client
 #[derive(Serialize, Deserialize, Clone)]
 pub enum ServerCommand {
-    UserMessage(User, Message),
+    /// A message posted in the room, with its id in the history of the server
+    UserMessage(User, Message, usize),
     ServerMessage(Message),
+    /// A direct message from a user to another, also sent back to the sender
+    DirectMessage(User, User, Message),
     UserList(Vec<(User, std::net::SocketAddr)>),
+    /// A user set their name for the first time, `time` being a unix timestamp in seconds
+    UserJoined {
+        user: User,
+        addr: std::net::SocketAddr,
+        time: i64,
+    },
+    UserLeft {
+        user: User,
+        addr: std::net::SocketAddr,
+        time: i64,
+    },
+    UserRenamed {
+        old: User,
+        new: User,
+        addr: std::net::SocketAddr,
+        time: i64,
+    },
     ServerName(String),
+    /// Acknowledges `ClientCommand::SetEncoding`, all the following frames use the encoding
+    Encoding(Encoding),
+    /// Messages found for a search, with its query, the best ranked first
+    SearchResults(String, Vec<SearchResult>),
     Error(String),
+    /// A message relayed to a linked server
+    Relay(Relay),
+}
+
+/// A message found by a search, `id` being its index in the history of the server
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SearchResult {
+    pub id: usize,
+    pub room: String,
+    pub user: User,
+    pub message: Message,
+    pub time: i64, // unix timestamp in seconds
+    pub score: f32,
+}
+
+/// Identifies a message among linked servers, so that relays looping back can be dropped
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RelayId {
+    /// name of the server where the message is posted
+    pub origin: String,
+    pub seq: u64,
+}
+
+/// A message relayed between linked servers
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Relay {
+    pub id: RelayId,
+    pub room: String,
+    pub user: User,
+    pub message: Message,
 }
 
 /// Peer (inside the server) needs to receive messages from...
 /// - client (client command)
 /// - server (notification)
 /// - other peers (message broadcast)
+/// - server administration (kick)
+/// - linked servers (relay)
 ///
 /// Use this enum to identify among them.
 #[derive(Clone)]
 pub enum Operation {
     FromClient(ClientCommand),
-    FromPeer(User, Message),
+    FromPeer(User, Message, usize),
     FromServer(ServerCommand),
+    /// the server asks to drop the connection, with the reason
+    Kick(String),
+    /// a message to relay to the linked server
+    Relay(Relay),
 }
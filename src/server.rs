@@ -1,7 +1,13 @@
+mod history;
+mod http;
+mod irc;
+mod link;
+mod websocket;
+
 use crate::error::*;
 
-use futures::SinkExt;
-use std::{collections::HashMap, net::SocketAddr};
+use futures::{future::BoxFuture, FutureExt, Sink, SinkExt};
+use std::{collections::HashMap, net::SocketAddr, path::Path};
 use std::{
     pin::Pin,
     sync::Arc,
@@ -10,23 +16,59 @@ use std::{
 use tokio::net::{TcpListener, TcpStream};
 use tokio::stream::{Stream, StreamExt};
 use tokio::sync::{mpsc, Mutex};
-use tokio_util::codec::{Framed, LinesCodec};
+use tokio_util::codec::Framed;
 
+use crate::codec::{self, ChatCodec, Encoding};
 use crate::message::*;
 use crate::protocol::*;
 
 type SharedState = Arc<Mutex<ServerState>>;
-type Transport = Framed<TcpStream, LinesCodec>;
+type TcpTransport = Framed<TcpStream, ChatCodec<ClientCommand>>;
 
 type Tx = mpsc::UnboundedSender<Operation>;
 type Rx = mpsc::UnboundedReceiver<Operation>;
 
+/// The only room of the server, which every peer is in
+const LOBBY: &str = "lobby";
+
+/// Suffix of the names of bots posting through the http api, which users can't take
+const BOT_SUFFIX: &str = "[bot]";
+
+/// Number of messages returned by a search, unless the client asks for another limit
+const SEARCH_LIMIT: usize = 20;
+const MAX_SEARCH_LIMIT: usize = 200;
+
+/// Error for messages which wouldn't fit in a frame once sent to the peers
+const TOO_LONG: &str = "The message is too long";
+
+/// A connection that a peer is served over, e.g. a framed tcp stream or a websocket
+trait Transport:
+    Stream<Item = Result<ClientCommand>> + Sink<ServerCommand, Error = Error> + Unpin + Send + 'static
+{
+    /// Whether the connection can switch to `encoding`
+    fn supports_encoding(&self, encoding: Encoding) -> bool;
+
+    /// Switch the wire encoding as requested by `ClientCommand::SetEncoding`
+    fn set_encoding(&mut self, encoding: Encoding);
+}
+
+impl Transport for TcpTransport {
+    fn supports_encoding(&self, _encoding: Encoding) -> bool {
+        true
+    }
+
+    fn set_encoding(&mut self, encoding: Encoding) {
+        self.codec_mut().set_encoding(encoding);
+    }
+}
+
 /// RecvPeer represents a registered user
-/// - transport: a framed tcp stream, used for communicating between server and client
+/// - transport: a connection like a framed tcp stream, used for communicating between server and client
 /// - rx: the recv half of the inter-peer channels, used for **receiving** broadcast messages from other peers
-struct RecvPeer {
-    transport: Transport,
+struct RecvPeer<T> {
+    transport: T,
     rx: Rx,
+    commands: usize, // number of commands received from the client
 }
 
 /// SendPeer will be used to broadcast from other peers
@@ -34,11 +76,12 @@ struct SendPeer {
     tx: Tx,
     username: User,
     addr: SocketAddr,
+    link: Option<String>, // name of the linked server, if this peer is a server link
 }
 
-impl RecvPeer {
+impl<T: Transport> RecvPeer<T> {
     /// Will allocate a channel, insert the send half into shared state, and return a Peer which owns the recv half and the transport
-    async fn register(state: SharedState, addr: SocketAddr, transport: Transport) -> Result<Self> {
+    async fn register(state: SharedState, addr: SocketAddr, transport: T) -> Result<Self> {
         let (tx, rx) = mpsc::unbounded_channel();
         state.lock().await.peers.insert(
             addr,
@@ -46,14 +89,19 @@ impl RecvPeer {
                 tx,
                 username: User::new(),
                 addr,
+                link: None,
             },
         );
 
-        Ok(Self { transport, rx })
+        Ok(Self {
+            transport,
+            rx,
+            commands: 0,
+        })
     }
 }
 
-impl Stream for RecvPeer {
+impl<T: Transport> Stream for RecvPeer<T> {
     type Item = Result<Operation>;
 
     /// Poll ServerOperation's from both transport and rx, so that we can use `next()` to receive all kinds of ops
@@ -66,11 +114,11 @@ impl Stream for RecvPeer {
         // Secondly poll the `Framed` stream.
         let result: Option<_> = futures::ready!(Pin::new(&mut self.transport).poll_next(cx));
         Poll::Ready(match result {
-            Some(Ok(de_str)) => {
-                let command = serde_json::from_str::<ClientCommand>(&de_str)?;
+            Some(Ok(command)) => {
+                self.commands += 1;
                 Some(Ok(Operation::FromClient(command)))
             }
-            Some(Err(e)) => Some(Err(e.into())),
+            Some(Err(e)) => Some(Err(e)),
             _ => None,
         })
     }
@@ -80,28 +128,123 @@ impl Stream for RecvPeer {
 #[derive(Default)]
 struct ServerState {
     name: String,
-    history: Vec<(User, Message)>,
+    history: history::History,
     peers: HashMap<SocketAddr, SendPeer>, // send halves of all peers
+    next_relay_seq: u64,
+    seen_relays: link::SeenRelays,
+    link_secret: Option<String>, // links are refused without it
 }
 
 impl ServerState {
-    /// Broadcast an operation to all peers through their send halves in the `state`
+    /// Broadcast an operation to all peers through their send halves in the `state`, except server links
     fn broadcast(&mut self, op: Operation, excludes: Vec<SocketAddr>) {
         for (_peer_addr, peer) in self.peers.iter_mut() {
-            if !excludes.contains(_peer_addr) {
+            if !excludes.contains(_peer_addr) && peer.link.is_none() {
                 let _ = peer.tx.send(op.clone());
             }
         }
     }
 
-    /// Broadcast the list of all online users
-    fn broadcast_user_list(&mut self) {
-        let users = self
-            .peers
+    /// Record a message from local `user` in the history, broadcast it to all peers and relay it to linked servers,
+    /// return its id in the history
+    fn post_message(&mut self, user: User, message: Message) -> usize {
+        let history_id = self.history.push(LOBBY, user.clone(), message.clone());
+        // send FromPeer ops to broadcast this message to all peers
+        let op = Operation::FromPeer(user.clone(), message.clone(), history_id);
+        self.broadcast(op, vec![]);
+
+        let id = RelayId {
+            origin: self.name.clone(),
+            seq: self.next_relay_seq,
+        };
+        self.next_relay_seq += 1;
+        self.seen_relays.insert(id.clone());
+        let relay = Relay {
+            id,
+            room: LOBBY.to_owned(),
+            user,
+            message,
+        };
+        self.relay(relay, None);
+        history_id
+    }
+
+    /// Post a message relayed from the linked server at `from` as `user@origin`, and relay it further
+    fn receive_relay(&mut self, mut relay: Relay, from: SocketAddr) {
+        // drop relays of other rooms or looping back
+        if relay.room != LOBBY || !self.seen_relays.insert(relay.id.clone()) {
+            return;
+        }
+        // only this server tells events to its users
+        if let Message::System(text) = relay.message {
+            relay.message = Message::Notice(text);
+        }
+        let user = format!("{}@{}", relay.user, relay.id.origin);
+        let id = self
+            .history
+            .push(&relay.room, user.clone(), relay.message.clone());
+        let op = Operation::FromPeer(user, relay.message.clone(), id);
+        self.broadcast(op, vec![]);
+        self.relay(relay, Some(from));
+    }
+
+    /// Send a relay to all linked servers, except the one it comes from
+    fn relay(&mut self, relay: Relay, from: Option<SocketAddr>) {
+        for (peer_addr, peer) in self.peers.iter() {
+            if peer.link.is_some() && Some(*peer_addr) != from {
+                let _ = peer.tx.send(Operation::Relay(relay.clone()));
+            }
+        }
+    }
+
+    /// All online users who have set their names
+    fn user_list(&self) -> Vec<(User, SocketAddr)> {
+        self.peers
             .values()
             .map(|p| (p.username.clone(), p.addr))
             .filter(|(n, _a)| !n.is_empty())
-            .collect();
+            .collect()
+    }
+
+    /// Whether a peer other than the one at `addr` is named `name`, ignoring the case
+    fn is_taken(&self, name: &str, addr: SocketAddr) -> bool {
+        self.peers
+            .values()
+            .any(|p| p.addr != addr && p.link.is_none() && p.username.eq_ignore_ascii_case(name))
+    }
+
+    /// Send a direct message to all peers named `to`, return whether there's any
+    fn send_direct(&mut self, from: User, to: User, message: Message) -> bool {
+        let mut sent = false;
+        let command = ServerCommand::DirectMessage(from, to.clone(), message);
+        for peer in self
+            .peers
+            .values()
+            .filter(|p| p.link.is_none() && !to.is_empty() && p.username == to)
+        {
+            let _ = peer.tx.send(Operation::FromServer(command.clone()));
+            sent = true;
+        }
+        sent
+    }
+
+    /// Disconnect all peers named `name`, return whether there's any
+    fn kick(&mut self, name: &str, reason: &str) -> bool {
+        let mut kicked = false;
+        for peer in self
+            .peers
+            .values()
+            .filter(|p| !name.is_empty() && p.username == name)
+        {
+            let _ = peer.tx.send(Operation::Kick(reason.to_owned()));
+            kicked = true;
+        }
+        kicked
+    }
+
+    /// Broadcast the list of all online users
+    fn broadcast_user_list(&mut self) {
+        let users = self.user_list();
         self.broadcast(
             Operation::FromServer(ServerCommand::UserList(users)),
             vec![],
@@ -112,6 +255,10 @@ impl ServerState {
 /// The chat server
 pub struct Server {
     listener: TcpListener,
+    ws_listener: Option<TcpListener>,
+    http_listener: Option<(TcpListener, String)>,
+    irc_listener: Option<TcpListener>,
+    links: Vec<String>,
     state: SharedState,
 }
 
@@ -120,15 +267,78 @@ impl Server {
     pub async fn new(port: u16, name: String) -> Result<Self> {
         Ok(Self {
             listener: TcpListener::bind(("0.0.0.0", port)).await?,
+            ws_listener: None,
+            http_listener: None,
+            irc_listener: None,
+            links: Vec::new(),
             state: Arc::new(Mutex::new(ServerState {
                 name,
+                // start from a random point so that relays after restarts won't be taken as seen
+                next_relay_seq: chrono::Utc::now().timestamp_nanos() as u64,
                 ..ServerState::default()
             })),
         })
     }
 
-    /// An infinite loop that accepts connections and then spawn tasks to process
+    /// Load the history from the file at `path`, and save the new messages to it
+    pub async fn history_file(&mut self, path: &Path) -> Result<()> {
+        self.state.lock().await.history = history::History::open(path)?;
+        Ok(())
+    }
+
+    /// Also accept websocket clients on another port, speaking the same json commands
+    pub async fn listen_websocket(&mut self, port: u16) -> Result<()> {
+        self.ws_listener = Some(TcpListener::bind(("0.0.0.0", port)).await?);
+        Ok(())
+    }
+
+    /// Also serve the http administration api on another port, authorized by `token`
+    pub async fn listen_http(&mut self, port: u16, token: String) -> Result<()> {
+        self.http_listener = Some((TcpListener::bind(("0.0.0.0", port)).await?, token));
+        Ok(())
+    }
+
+    /// Also accept IRC clients on another port
+    pub async fn listen_irc(&mut self, port: u16) -> Result<()> {
+        self.irc_listener = Some(TcpListener::bind(("0.0.0.0", port)).await?);
+        Ok(())
+    }
+
+    /// Link to another server at `remote` (host:port) and relay messages of the lobby
+    pub fn link(&mut self, remote: String) {
+        self.links.push(remote);
+    }
+
+    /// Set the secret that servers linking to this one, and this one to others, must give
+    pub async fn link_secret(&mut self, secret: String) -> Result<()> {
+        if secret.is_empty() {
+            return Err(Error::ConfigError("the link secret is empty".to_owned()));
+        }
+        self.state.lock().await.link_secret = Some(secret);
+        Ok(())
+    }
+
+    /// Run all listeners of the server until one of them fails
     pub async fn run(&self) -> Result<()> {
+        let mut listeners: Vec<BoxFuture<Result<()>>> = vec![self.accept_tcp().boxed()];
+        if let Some(ws_listener) = &self.ws_listener {
+            listeners.push(websocket::accept(ws_listener, self.state.clone()).boxed());
+        }
+        if let Some((http_listener, token)) = &self.http_listener {
+            listeners.push(http::accept(http_listener, token, self.state.clone()).boxed());
+        }
+        if let Some(irc_listener) = &self.irc_listener {
+            listeners.push(irc::accept(irc_listener, self.state.clone()).boxed());
+        }
+        for remote in &self.links {
+            listeners.push(link::connect(remote, self.state.clone()).boxed());
+        }
+        futures::future::try_join_all(listeners).await?;
+        Ok(())
+    }
+
+    /// An infinite loop that accepts connections and then spawn tasks to process
+    async fn accept_tcp(&self) -> Result<()> {
         log::info!("listen on {:?}", self.listener.local_addr()?);
 
         loop {
@@ -136,27 +346,44 @@ impl Server {
             let arc_state = self.state.clone();
             // spawn a new task to handle the connection
             tokio::spawn(async move {
-                let transport = Framed::new(stream, LinesCodec::new());
+                let transport: TcpTransport = Framed::new(stream, ChatCodec::new());
                 let _ = Self::handle(transport, addr, arc_state).await;
             });
         }
     }
 
     /// Connection handler
-    async fn handle(transport: Transport, addr: SocketAddr, state: SharedState) -> Result<()> {
+    async fn handle<T: Transport>(
+        transport: T,
+        addr: SocketAddr,
+        state: SharedState,
+    ) -> Result<()> {
         let mut peer = RecvPeer::register(state.clone(), addr, transport).await?; // register the new peer in shared state
         let mut name = "".to_string();
+        let mut link: Option<String> = None; // name of the linked server, if the peer is a server
 
         macro_rules! log{
             ($level:ident, $($x:expr),+) => {
                 log::$level!("[{}({})] {}", addr, name, format!($($x),+));
             }
         }
+        // a command too large for a frame is replaced with an error, and any other failure
+        // ends the connection, the peer being removed below
         macro_rules! send {
             ($msg:expr) => {
-                peer.transport
-                    .send(serde_json::to_string(&$msg).unwrap())
-                    .await?;
+                let sent = match peer.transport.send($msg).await {
+                    Err(Error::FrameTooLarge) => {
+                        log!(warn, "dropped a command too large to be sent");
+                        let e =
+                            ServerCommand::Error("The reply is too large to be sent".to_owned());
+                        peer.transport.send(e).await
+                    }
+                    sent => sent,
+                };
+                if let Err(e) = sent {
+                    log!(warn, "send error: {}", e);
+                    break;
+                }
             };
         }
 
@@ -171,62 +398,221 @@ impl Server {
                         Operation::FromClient(command) => match command {
                             // set client's name
                             ClientCommand::SetName(new_name) => {
-                                if new_name.is_empty() {
+                                if new_name.is_empty() || new_name == name || link.is_some() {
                                     continue;
                                 }
-                                log!(info, "change name to: {}", new_name);
 
                                 {
                                     let mut state = state.lock().await;
+                                    if new_name.ends_with(BOT_SUFFIX) {
+                                        send!(ServerCommand::Error(format!(
+                                            "Names ending with `{}` are reserved for bots",
+                                            BOT_SUFFIX
+                                        )));
+                                        continue;
+                                    }
+                                    // `user@server` are the users of linked servers
+                                    if new_name.contains('@') {
+                                        send!(ServerCommand::Error(
+                                            "Names with `@` are reserved for linked servers"
+                                                .to_owned()
+                                        ));
+                                        continue;
+                                    }
+                                    if state.is_taken(&new_name, addr) {
+                                        log!(info, "name taken: {}", new_name);
+                                        send!(ServerCommand::Error(format!(
+                                            "The name `{}` is taken",
+                                            new_name
+                                        )));
+                                        continue;
+                                    }
+                                    log!(info, "change name to: {}", new_name);
                                     if let Some(send_peer) = state.peers.get_mut(&addr) {
                                         send_peer.username = new_name.clone(); // record new name in state
                                     }
 
+                                    let time = chrono::Utc::now().timestamp();
                                     // newly incoming user
                                     if name.is_empty() {
                                         state.broadcast(
-                                            Operation::FromServer(ServerCommand::ServerMessage(
-                                                Message::Text(format!("Welcome, {}!", new_name)),
-                                            )),
+                                            Operation::FromServer(ServerCommand::UserJoined {
+                                                user: new_name.clone(),
+                                                addr,
+                                                time,
+                                            }),
                                             vec![],
                                         );
                                         // tell the server name
-                                        send!(&ServerCommand::ServerName(state.name.clone()));
+                                        send!(ServerCommand::ServerName(state.name.clone()));
+                                    } else {
+                                        state.broadcast(
+                                            Operation::FromServer(ServerCommand::UserRenamed {
+                                                old: name.clone(),
+                                                new: new_name.clone(),
+                                                addr,
+                                                time,
+                                            }),
+                                            vec![],
+                                        );
                                     }
                                     state.broadcast_user_list();
                                 }
 
                                 name = new_name;
                             }
+                            // switch the wire encoding, acknowledged in the current one so that
+                            // the client switches right after the frames sent before
+                            ClientCommand::SetEncoding(encoding) if peer.commands == 1 => {
+                                if peer.transport.supports_encoding(encoding) {
+                                    log!(info, "switch encoding to: {:?}", encoding);
+                                    send!(ServerCommand::Encoding(encoding));
+                                    peer.transport.set_encoding(encoding);
+                                } else {
+                                    let e = Error::UnsupportedEncoding(encoding);
+                                    log!(warn, "error: {}", e);
+                                    send!(ServerCommand::Error(e.to_string()));
+                                }
+                            }
+                            ClientCommand::SetEncoding(_) => {
+                                send!(ServerCommand::Error(
+                                    "The encoding can only be set by the first command".to_owned()
+                                ));
+                            }
+                            // another server links to this one
+                            ClientCommand::Link { server, secret } => {
+                                let mut state = state.lock().await;
+                                let trusted = (state.link_secret.as_deref())
+                                    .is_some_and(|s| crate::utils::secrets_eq(s, &secret));
+                                // a user can't turn into a server, nor a server link twice
+                                let refused = if !trusted {
+                                    Some("wrong link secret")
+                                } else if !name.is_empty() || link.is_some() {
+                                    Some("already joined")
+                                } else {
+                                    None
+                                };
+                                if let Some(reason) = refused {
+                                    log!(warn, "link refused: {}: {}", server, reason);
+                                    send!(ServerCommand::Error(reason.to_owned()));
+                                    continue;
+                                }
+                                log!(info, "linked by server: {}", server);
+                                if let Some(send_peer) = state.peers.get_mut(&addr) {
+                                    send_peer.link = Some(server.clone());
+                                }
+                                send!(ServerCommand::ServerName(state.name.clone()));
+                                link = Some(server);
+                            }
+                            // message relayed from the linked server
+                            ClientCommand::Relay(relay) if link.is_some() => {
+                                state.lock().await.receive_relay(relay, addr);
+                            }
                             // commands requested without name are ignored
                             _ if name.is_empty() => {
                                 continue;
                             }
+                            ClientCommand::SendMessage(Message::System(_))
+                            | ClientCommand::SendDirect(_, Message::System(_)) => {
+                                send!(ServerCommand::Error(
+                                    "System messages are only sent by the server".to_owned()
+                                ));
+                            }
                             // message from client
                             ClientCommand::SendMessage(message) => {
-                                let mut state = state.lock().await;
-                                state.history.push((name.clone(), message.clone()));
-                                // send FromPeer ops to broadcast this message to all peers
-                                state.broadcast(
-                                    Operation::FromPeer(name.clone(), message.clone()),
-                                    vec![],
+                                // the broadcast of the message must fit in a frame, whatever its id
+                                let broadcast = ServerCommand::UserMessage(
+                                    name.clone(),
+                                    message.clone(),
+                                    usize::MAX,
                                 );
+                                if !codec::fits_in_frame(&broadcast) {
+                                    send!(ServerCommand::Error(TOO_LONG.to_owned()));
+                                    continue;
+                                }
                                 log!(info, "{:?}", message);
+                                state.lock().await.post_message(name.clone(), message);
                             }
+                            // direct message from client, echoed back unless sent to itself
+                            ClientCommand::SendDirect(to, message) => {
+                                let direct = ServerCommand::DirectMessage(
+                                    name.clone(),
+                                    to.clone(),
+                                    message.clone(),
+                                );
+                                if !codec::fits_in_frame(&direct) {
+                                    send!(ServerCommand::Error(TOO_LONG.to_owned()));
+                                    continue;
+                                }
+                                log!(info, "to {}: {:?}", to, message);
+                                let sent = state.lock().await.send_direct(
+                                    name.clone(),
+                                    to.clone(),
+                                    message.clone(),
+                                );
+                                if !sent {
+                                    send!(ServerCommand::Error(format!("No such user `{}`", to)));
+                                } else if to != name {
+                                    send!(ServerCommand::DirectMessage(name.clone(), to, message));
+                                }
+                            }
+                            // full-text search over the history
+                            ClientCommand::Search {
+                                query,
+                                room,
+                                author,
+                                since,
+                                until,
+                                limit,
+                            } => {
+                                let filter = history::Filter {
+                                    room,
+                                    author,
+                                    since,
+                                    until,
+                                };
+                                let limit = limit.unwrap_or(SEARCH_LIMIT).min(MAX_SEARCH_LIMIT);
+                                let results =
+                                    state.lock().await.history.search(&query, &filter, limit);
+                                send!(ServerCommand::SearchResults(query, results));
+                            }
+                            // relays are only accepted from linked servers
+                            ClientCommand::Relay(_) => {}
                         },
                         // a broadcast from other peers
-                        Operation::FromPeer(user, message) => {
-                            send!(&ServerCommand::UserMessage(user, message));
+                        Operation::FromPeer(user, message, id) => {
+                            send!(ServerCommand::UserMessage(user, message, id));
                         }
                         // a message from server itself, straightly forward to the client
                         Operation::FromServer(message) => {
                             send!(message);
                         }
+                        // a message to relay to the linked server
+                        Operation::Relay(relay) => {
+                            send!(ServerCommand::Relay(relay));
+                        }
+                        // kicked by the server, say goodbye and close the connection
+                        Operation::Kick(reason) => {
+                            log!(info, "kicked: {}", reason);
+                            send!(ServerCommand::ServerMessage(Message::System(format!(
+                                "You were kicked: {}",
+                                reason
+                            ))));
+                            break;
+                        }
                     }
                 }
+                Err(e) if e.is_fatal() => {
+                    log!(warn, "fatal error: {}", e);
+                    let _ = peer
+                        .transport
+                        .send(ServerCommand::Error(e.to_string()))
+                        .await;
+                    break;
+                }
                 Err(e) => {
                     log!(warn, "error: {}", e);
-                    send!(&ServerCommand::Error("What's that?".to_owned(),));
+                    send!(ServerCommand::Error("What's that?".to_owned(),));
                 }
             }
         }
@@ -236,15 +622,135 @@ impl Server {
             let mut state = state.lock().await;
             state.peers.remove(&addr);
 
-            // broadcast left message
-            let leave_msg = Message::Text(format!("{} left.", name));
-            let op = Operation::FromServer(ServerCommand::ServerMessage(leave_msg));
-            state.broadcast(op, vec![]);
+            // tell the others, if the peer has ever joined as a user
+            if !name.is_empty() {
+                let op = Operation::FromServer(ServerCommand::UserLeft {
+                    user: name.clone(),
+                    addr,
+                    time: chrono::Utc::now().timestamp(),
+                });
+                state.broadcast(op, vec![]);
 
-            state.broadcast_user_list();
+                state.broadcast_user_list();
+            }
             log!(info, "left");
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::MAX_FRAME_LENGTH;
+    use std::time::Duration;
+
+    type Client = Framed<TcpStream, ChatCodec<ServerCommand>>;
+
+    async fn start() -> (u16, SharedState) {
+        let server = Server::new(0, "test".to_owned()).await.unwrap();
+        let port = server.listener.local_addr().unwrap().port();
+        let state = server.state.clone();
+        tokio::spawn(async move { server.run().await });
+        (port, state)
+    }
+
+    async fn join(port: u16, name: &str) -> Client {
+        let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let mut client = Framed::new(stream, ChatCodec::new());
+        client
+            .send(ClientCommand::SetName(name.to_owned()))
+            .await
+            .unwrap();
+        expect(&mut client, |c| matches!(c, ServerCommand::ServerName(_))).await;
+        client
+    }
+
+    /// Read commands until one matches, failing if the connection ends or it takes too long
+    async fn expect(
+        client: &mut Client,
+        matching: impl Fn(&ServerCommand) -> bool,
+    ) -> ServerCommand {
+        let read = async {
+            loop {
+                match client.next().await {
+                    Some(Ok(command)) if matching(&command) => return command,
+                    Some(Ok(_)) => {}
+                    _ => panic!("disconnected"),
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), read)
+            .await
+            .expect("timed out")
+    }
+
+    fn text(text: String) -> ClientCommand {
+        ClientCommand::SendMessage(Message::Text(text))
+    }
+
+    #[tokio::test]
+    async fn reserved_names_are_rejected() {
+        let (port, _) = start().await;
+        let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let mut client: Client = Framed::new(stream, ChatCodec::new());
+        for name in ["bob@office", "bob[bot]"] {
+            client
+                .send(ClientCommand::SetName(name.to_owned()))
+                .await
+                .unwrap();
+            expect(&mut client, |c| matches!(c, ServerCommand::Error(_))).await;
+        }
+        client
+            .send(ClientCommand::SetName("bob".to_owned()))
+            .await
+            .unwrap();
+        expect(&mut client, |c| matches!(c, ServerCommand::ServerName(_))).await;
+    }
+
+    #[tokio::test]
+    async fn oversized_replies_keep_peers_connected() {
+        let (port, state) = start().await;
+        let mut alice = join(port, "alice").await;
+        let mut bob = join(port, "bob").await;
+
+        // fits in an inbound frame, but not once sent as a `UserMessage`
+        let long = "x".repeat(MAX_FRAME_LENGTH - 30);
+        alice.send(text(long)).await.unwrap();
+        let error = expect(&mut alice, |c| matches!(c, ServerCommand::Error(_))).await;
+        assert!(matches!(error, ServerCommand::Error(e) if e == TOO_LONG));
+
+        // too many long messages for one `SearchResults`
+        for _ in 0..3 {
+            alice
+                .send(text("y".repeat(MAX_FRAME_LENGTH / 3)))
+                .await
+                .unwrap();
+        }
+        alice
+            .send(ClientCommand::Search {
+                query: String::new(),
+                room: None,
+                author: None,
+                since: None,
+                until: None,
+                limit: None,
+            })
+            .await
+            .unwrap();
+        expect(&mut alice, |c| matches!(c, ServerCommand::Error(_))).await;
+
+        // both are still served
+        alice.send(text("hi".to_owned())).await.unwrap();
+        let hi = |c: &ServerCommand| matches!(c, ServerCommand::UserMessage(_, m, _) if m.text() == "hi");
+        expect(&mut alice, hi).await;
+        expect(&mut bob, hi).await;
+        assert_eq!(state.lock().await.peers.len(), 2);
+
+        // and a peer leaving is still removed
+        drop(bob);
+        expect(&mut alice, |c| matches!(c, ServerCommand::UserLeft { .. })).await;
+        assert_eq!(state.lock().await.user_list().len(), 1);
+    }
+}
@@ -0,0 +1,281 @@
+//! The message history of the server, with an inverted index for full-text search
+//!
+//! If the server is given a history file, every message is appended to it as a JSON line
+//! by a writer task, so that saving never blocks the server, and the history is loaded back
+//! from it on start.
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+
+use crate::error::*;
+use crate::message::*;
+use crate::protocol::SearchResult;
+
+/// A message in the history, its id being its index
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(super) struct Record {
+    pub id: usize,
+    pub room: String,
+    pub user: User,
+    pub message: Message,
+    pub time: i64, // unix timestamp in seconds
+}
+
+/// Conditions on the messages found by a search
+#[derive(Default, Debug)]
+pub(super) struct Filter {
+    pub room: Option<String>,
+    pub author: Option<User>,
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+}
+
+impl Filter {
+    fn accepts(&self, record: &Record) -> bool {
+        self.room.as_ref().is_none_or(|room| record.room == *room)
+            && (self.author.as_ref()).is_none_or(|author| record.user.eq_ignore_ascii_case(author))
+            && self.since.is_none_or(|since| record.time >= since)
+            && self.until.is_none_or(|until| record.time <= until)
+    }
+}
+
+#[derive(Default)]
+pub(super) struct History {
+    records: Vec<Record>,
+    index: HashMap<String, Vec<(usize, u32)>>, // term -> ids of the messages with its count, by id
+    writer: Option<mpsc::UnboundedSender<String>>, // lines to append to the file
+}
+
+impl History {
+    /// Load the history from the file at `path`, and append the new messages to it
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut history = Self::default();
+        if path.exists() {
+            for (i, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+                match serde_json::from_str::<Record>(&line?) {
+                    Ok(record) => history.insert(record),
+                    Err(e) => log::warn!("{}:{}: invalid history: {}", path.display(), i + 1, e),
+                }
+            }
+            log::info!("loaded {} messages from {}", history.len(), path.display());
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        history.writer = Some(Self::spawn_writer(tokio::fs::File::from_std(file)));
+        Ok(history)
+    }
+
+    /// Append the lines sent to the returned channel to `file`, until it's dropped
+    fn spawn_writer(mut file: tokio::fs::File) -> mpsc::UnboundedSender<String> {
+        let (tx, mut rx) = mpsc::unbounded_channel::<String>();
+        tokio::spawn(async move {
+            while let Some(line) = rx.recv().await {
+                // a write of tokio's `File` only completes on flush
+                let written = async {
+                    file.write_all(line.as_bytes()).await?;
+                    file.flush().await
+                };
+                if let Err(e) = written.await {
+                    log::warn!("failed to save the history: {}", e);
+                }
+            }
+        });
+        tx
+    }
+
+    /// Record a message, return its id
+    pub fn push(&mut self, room: &str, user: User, message: Message) -> usize {
+        let record = Record {
+            id: self.records.len(),
+            room: room.to_owned(),
+            user,
+            message,
+            time: chrono::Utc::now().timestamp(),
+        };
+        if let Some(writer) = &self.writer {
+            let _ = writer.send(serde_json::to_string(&record).unwrap() + "\n");
+        }
+        let id = record.id;
+        self.insert(record);
+        id
+    }
+
+    fn insert(&mut self, mut record: Record) {
+        // ids are renumbered, in case the file has been edited
+        record.id = self.records.len();
+        let mut counts: HashMap<String, u32> = HashMap::new();
+        for term in terms(record.message.text()) {
+            *counts.entry(term).or_default() += 1;
+        }
+        for (term, count) in counts {
+            self.index.entry(term).or_default().push((record.id, count));
+        }
+        self.records.push(record);
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn records(&self) -> &[Record] {
+        &self.records
+    }
+
+    /// Messages containing all the words of `query` and accepted by `filter`, ranked by
+    /// tf-idf then the newest first. An empty query finds all the accepted messages.
+    pub fn search(&self, query: &str, filter: &Filter, limit: usize) -> Vec<SearchResult> {
+        let mut terms: Vec<_> = terms(query).collect();
+        terms.sort_unstable();
+        terms.dedup();
+
+        let mut scored: Vec<(usize, f32)> = if terms.is_empty() {
+            (0..self.records.len()).map(|id| (id, 0.0)).collect()
+        } else {
+            let postings = match (terms.iter())
+                .map(|term| self.index.get(term))
+                .collect::<Option<Vec<_>>>()
+            {
+                Some(postings) => postings,
+                None => return Vec::new(), // some word is in no message
+            };
+            let total = self.records.len() as f32;
+            let idf = |posting: &Vec<(usize, u32)>| (1.0 + total / posting.len() as f32).ln();
+
+            // walk the shortest posting list, looking up the others
+            let shortest = postings.iter().min_by_key(|p| p.len()).unwrap();
+            shortest
+                .iter()
+                .filter_map(|(id, _)| {
+                    postings
+                        .iter()
+                        .try_fold(0.0, |score, posting| {
+                            let i = posting.binary_search_by_key(id, |(id, _)| *id).ok()?;
+                            Some(score + posting[i].1 as f32 * idf(posting))
+                        })
+                        .map(|score| (*id, score))
+                })
+                .collect()
+        };
+
+        scored.retain(|(id, _)| filter.accepts(&self.records[*id]));
+        scored.sort_by(|(a_id, a), (b_id, b)| b.total_cmp(a).then(b_id.cmp(a_id)));
+        scored
+            .into_iter()
+            .take(limit)
+            .map(|(id, score)| {
+                let record = self.records[id].clone();
+                SearchResult {
+                    id,
+                    room: record.room,
+                    user: record.user,
+                    message: record.message,
+                    time: record.time,
+                    score,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Lowercase words of a text
+fn terms(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A history of texts by alice in the lobby, posted at times 100, 200, ...
+    fn posted(texts: &[&str]) -> History {
+        let mut history = History::default();
+        for (i, text) in texts.iter().enumerate() {
+            history.insert(Record {
+                id: 0,
+                room: "lobby".to_owned(),
+                user: "alice".to_owned(),
+                message: Message::Text((*text).to_owned()),
+                time: 100 * (i as i64 + 1),
+            });
+        }
+        history
+    }
+
+    fn ids(results: Vec<SearchResult>) -> Vec<usize> {
+        results.into_iter().map(|result| result.id).collect()
+    }
+
+    #[test]
+    fn tokenize() {
+        let words: Vec<_> = terms("Hello, World! l'été 42_x").collect();
+        assert_eq!(words, ["hello", "world", "l", "été", "42", "x"]);
+        assert_eq!(terms(" .. ").count(), 0);
+    }
+
+    #[test]
+    fn ranking() {
+        let history = posted(&["rust is fast", "Rust, rust and async", "python async"]);
+        let all = Filter::default();
+        // more occurrences rank first, then the newest
+        assert_eq!(ids(history.search("rust", &all, 10)), [1, 0]);
+        assert_eq!(ids(history.search("ASYNC", &all, 10)), [2, 1]);
+        // all the words must be found
+        assert_eq!(ids(history.search("rust async", &all, 10)), [1]);
+        assert!(history.search("rust java", &all, 10).is_empty());
+        assert_eq!(ids(history.search("", &all, 10)), [2, 1, 0]);
+
+        // rarer words weigh more: `y` is in 2 messages out of 3, `x` in all of them
+        let history = posted(&["x y y", "x x y", "x"]);
+        let results = history.search("x y", &all, 10);
+        assert!(results[0].score > results[1].score);
+        assert_eq!(ids(results), [0, 1]);
+    }
+
+    #[test]
+    fn filters() {
+        let mut history = posted(&["a", "b", "c"]);
+        history.insert(Record {
+            id: 0,
+            room: "other".to_owned(),
+            user: "bob".to_owned(),
+            message: Message::Text("d".to_owned()),
+            time: 400,
+        });
+        let search = |filter: Filter, limit: usize| ids(history.search("", &filter, limit));
+
+        let since = |since| Filter {
+            since: Some(since),
+            ..Filter::default()
+        };
+        assert_eq!(search(since(200), 10), [3, 2, 1]);
+        assert_eq!(search(since(200), 2), [3, 2]);
+        let until = Filter {
+            until: Some(200),
+            ..Filter::default()
+        };
+        assert_eq!(search(until, 10), [1, 0]);
+        let between = Filter {
+            since: Some(150),
+            until: Some(250),
+            ..Filter::default()
+        };
+        assert_eq!(search(between, 10), [1]);
+        let author = Filter {
+            author: Some("ALICE".to_owned()),
+            ..Filter::default()
+        };
+        assert_eq!(search(author, 10), [2, 1, 0]);
+        let room = Filter {
+            room: Some("other".to_owned()),
+            ..Filter::default()
+        };
+        assert_eq!(search(room, 10), [3]);
+        assert!(search(Filter::default(), 0).is_empty());
+    }
+}
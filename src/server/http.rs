@@ -0,0 +1,382 @@
+//! A minimal embedded http server for administration and integrations
+//!
+//! Every request must carry `Authorization: Bearer <token>`. Routes:
+//! - `GET /users`: online users
+//! - `GET /rooms`: rooms of the server
+//! - `GET /history?offset=&limit=`: a page of the message history, oldest first
+//! - `POST /messages` with `{"user": .., "text": ..}`: post a message as a named bot, with
+//!   an optional `"kind"` of `text` (the default), `action` or `notice`. The name of the bot
+//!   is suffixed with `[bot]`, which users can't take, so that it can't pass for a user.
+//! - `POST /users/<name>/kick` with optional `{"reason": ..}`: disconnect a user
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use super::{SharedState, BOT_SUFFIX, LOBBY};
+use crate::codec::MAX_FRAME_LENGTH;
+use crate::error::*;
+use crate::message::*;
+
+const MAX_HEAD_LENGTH: usize = 16 * 1024;
+const MAX_HEADERS: usize = 32;
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 500;
+/// How long a client has to send its whole request, so that idle ones don't hold a task
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+struct Request {
+    method: String,
+    path: String,
+    query: HashMap<String, String>,
+    token: Option<String>,
+    body: Vec<u8>,
+}
+
+struct Response {
+    status: u16,
+    body: Value,
+}
+
+impl Response {
+    fn ok(body: Value) -> Self {
+        Self { status: 200, body }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Self {
+            status,
+            body: json!({ "error": message }),
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let reason = match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            404 => "Not Found",
+            408 => "Request Timeout",
+            413 => "Payload Too Large",
+            431 => "Request Header Fields Too Large",
+            _ => "Internal Server Error",
+        };
+        let body = self.body.to_string();
+        format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.status,
+            reason,
+            body.len(),
+            body
+        )
+        .into_bytes()
+    }
+}
+
+/// An infinite loop that accepts http connections, each serving a single request
+pub(super) async fn accept(listener: &TcpListener, token: &str, state: SharedState) -> Result<()> {
+    log::info!("listen on {:?} for http", listener.local_addr()?);
+
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        let token = token.to_owned();
+        let arc_state = state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve(stream, addr, &token, arc_state).await {
+                log::warn!("[{}] http error: {}", addr, e);
+            }
+        });
+    }
+}
+
+async fn serve(
+    mut stream: TcpStream,
+    addr: SocketAddr,
+    token: &str,
+    state: SharedState,
+) -> Result<()> {
+    let request = tokio::time::timeout(REQUEST_TIMEOUT, read_request(&mut stream)).await;
+    let request = request.unwrap_or_else(|_| Ok(Err(Response::error(408, "request timeout"))));
+    let response = match request? {
+        Ok(request) => {
+            let response = if authorized(&request, token) {
+                route(&request, state).await
+            } else {
+                Response::error(401, "invalid token")
+            };
+            log::info!(
+                "[{}] {} {} -> {}",
+                addr,
+                request.method,
+                request.path,
+                response.status
+            );
+            response
+        }
+        Err(response) => response,
+    };
+
+    stream.write_all(&response.to_bytes()).await?;
+    stream.shutdown(std::net::Shutdown::Write)?;
+    Ok(())
+}
+
+/// Read and parse a request, or return the error response for a malformed one
+async fn read_request(stream: &mut TcpStream) -> Result<std::result::Result<Request, Response>> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+
+    // read until the head is complete
+    let (mut request, head_length, content_length) = loop {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok(Err(Response::error(400, "incomplete request")));
+        }
+        buf.extend_from_slice(&chunk[..n]);
+
+        let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+        let mut parsed = httparse::Request::new(&mut headers);
+        match parsed.parse(&buf) {
+            Ok(httparse::Status::Complete(head_length)) => {
+                let header = |name: &str| {
+                    parsed
+                        .headers
+                        .iter()
+                        .find(|h| h.name.eq_ignore_ascii_case(name))
+                        .and_then(|h| std::str::from_utf8(h.value).ok())
+                        .map(|v| v.trim().to_owned())
+                };
+                let content_length = match header("content-length").map(|v| v.parse::<usize>()) {
+                    None => 0,
+                    Some(Ok(length)) => length,
+                    Some(Err(_)) => return Ok(Err(Response::error(400, "invalid content length"))),
+                };
+                let token = header("authorization")
+                    .and_then(|v| v.strip_prefix("Bearer ").map(|t| t.trim().to_owned()));
+                let target = parsed.path.unwrap_or("/");
+                let (path, query) = match target.find('?') {
+                    Some(i) => (&target[..i], parse_query(&target[i + 1..])),
+                    None => (target, HashMap::new()),
+                };
+                let request = Request {
+                    method: parsed.method.unwrap_or("").to_owned(),
+                    path: path.to_owned(),
+                    query,
+                    token,
+                    body: Vec::new(),
+                };
+                break (request, head_length, content_length);
+            }
+            Ok(httparse::Status::Partial) if buf.len() < MAX_HEAD_LENGTH => {}
+            Ok(httparse::Status::Partial) => {
+                return Ok(Err(Response::error(431, "request head too large")));
+            }
+            Err(e) => return Ok(Err(Response::error(400, &e.to_string()))),
+        }
+    };
+
+    if content_length > MAX_FRAME_LENGTH {
+        return Ok(Err(Response::error(413, "request body too large")));
+    }
+
+    // then read the rest of the body
+    let mut body = buf.split_off(head_length);
+    while body.len() < content_length {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok(Err(Response::error(400, "incomplete request body")));
+        }
+        body.extend_from_slice(&chunk[..n]);
+    }
+    body.truncate(content_length);
+    request.body = body;
+
+    Ok(Ok(request))
+}
+
+fn authorized(request: &Request, token: &str) -> bool {
+    (request.token.as_deref()).is_some_and(|t| crate::utils::secrets_eq(t, token))
+}
+
+async fn route(request: &Request, state: SharedState) -> Response {
+    let segments: Vec<String> = request
+        .path
+        .trim_matches('/')
+        .split('/')
+        .map(percent_decode)
+        .collect();
+    let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+
+    match (request.method.as_str(), segments.as_slice()) {
+        ("GET", ["users"]) => {
+            let state = state.lock().await;
+            let users: Vec<_> = state
+                .user_list()
+                .into_iter()
+                .map(|(name, addr)| json!({ "name": name, "addr": addr }))
+                .collect();
+            Response::ok(json!(users))
+        }
+
+        ("GET", ["rooms"]) => {
+            let state = state.lock().await;
+            Response::ok(json!([{
+                "name": LOBBY,
+                "users": state.user_list().len(),
+                "messages": state.history.len(),
+            }]))
+        }
+
+        ("GET", ["history"]) => {
+            let number = |key: &str, default: usize| match request.query.get(key) {
+                Some(v) => v.parse::<usize>().map_err(|_| format!("invalid `{}`", key)),
+                None => Ok(default),
+            };
+            let (offset, limit) = match (number("offset", 0), number("limit", DEFAULT_PAGE_SIZE)) {
+                (Ok(offset), Ok(limit)) => (offset, limit.min(MAX_PAGE_SIZE)),
+                (Err(e), _) | (_, Err(e)) => return Response::error(400, &e),
+            };
+
+            let state = state.lock().await;
+            let messages: Vec<_> = state
+                .history
+                .records()
+                .iter()
+                .skip(offset)
+                .take(limit)
+                .map(|r| {
+                    json!({
+                        "id": r.id,
+                        "user": r.user,
+                        "kind": r.message.kind(),
+                        "text": r.message.text(),
+                        "time": r.time,
+                    })
+                })
+                .collect();
+            Response::ok(json!({
+                "total": state.history.len(),
+                "offset": offset,
+                "messages": messages,
+            }))
+        }
+
+        ("POST", ["messages"]) => {
+            #[derive(Deserialize)]
+            struct Post {
+                user: User,
+                text: String,
+                kind: Option<String>,
+            }
+            let post: Post = match serde_json::from_slice(&request.body) {
+                Ok(post) => post,
+                Err(e) => return Response::error(400, &e.to_string()),
+            };
+            if post.user.trim().is_empty() || post.text.trim().is_empty() {
+                return Response::error(400, "`user` and `text` must not be empty");
+            }
+
+            let message = match post.kind.as_deref().unwrap_or("text") {
+                "text" => Message::Text(post.text),
+                "action" => Message::Action(post.text),
+                "notice" => Message::Notice(post.text),
+                _ => return Response::error(400, "`kind` must be text, action or notice"),
+            };
+
+            let user = format!("{}{}", post.user.trim(), BOT_SUFFIX);
+            let mut state = state.lock().await;
+            let id = state.post_message(user.clone(), message);
+            Response::ok(json!({ "id": id, "user": user }))
+        }
+
+        ("POST", ["users", name, "kick"]) => {
+            #[derive(Deserialize, Default)]
+            struct Kick {
+                reason: Option<String>,
+            }
+            let kick: Kick = if request.body.is_empty() {
+                Kick::default()
+            } else {
+                match serde_json::from_slice(&request.body) {
+                    Ok(kick) => kick,
+                    Err(e) => return Response::error(400, &e.to_string()),
+                }
+            };
+            let reason = kick.reason.unwrap_or_else(|| "kicked by admin".to_owned());
+
+            if state.lock().await.kick(name, &reason) {
+                Response::ok(json!({ "kicked": name }))
+            } else {
+                Response::error(404, "no such user")
+            }
+        }
+
+        _ => Response::error(404, "not found"),
+    }
+}
+
+/// Decode a query string, where `+` is a space unlike in the path
+fn parse_query(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| pair.replace('+', " "))
+        .map(|pair| match pair.find('=') {
+            Some(i) => (percent_decode(&pair[..i]), percent_decode(&pair[i + 1..])),
+            None => (percent_decode(&pair), String::new()),
+        })
+        .collect()
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            // only two hex digits, `from_str_radix` would take a sign
+            b'%' if i + 2 < bytes.len() => match (hex_digit(bytes[i + 1]), hex_digit(bytes[i + 2]))
+            {
+                (Some(high), Some(low)) => {
+                    decoded.push(high << 4 | low);
+                    i += 3;
+                    continue;
+                }
+                _ => decoded.push(b'%'),
+            },
+            b => decoded.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn hex_digit(b: u8) -> Option<u8> {
+    (b as char).to_digit(16).map(|d| d as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plus_is_a_space_only_in_queries() {
+        let query = parse_query("q=a+b%2Bc&limit=5&flag");
+        assert_eq!(query["q"], "a b+c");
+        assert_eq!(query["limit"], "5");
+        assert_eq!(query["flag"], "");
+        assert_eq!(percent_decode("c++%20dev"), "c++ dev");
+    }
+
+    #[test]
+    fn invalid_escapes_are_kept() {
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz%41"), "%zzA");
+        assert_eq!(percent_decode("%+1%-1"), "%+1%-1");
+        assert_eq!(percent_decode("caf%C3%A9"), "café");
+    }
+}
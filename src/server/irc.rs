@@ -0,0 +1,546 @@
+//! An IRC gateway, so that standard IRC clients can talk to chat users
+//!
+//! The only room of the server is exposed as the channel `#lobby`. Supported commands are
+//! NICK, USER, JOIN, PART, PRIVMSG, NOTICE, NAMES, PING, PONG and QUIT, a PRIVMSG to a nick
+//! being sent as a direct message, and a CTCP ACTION as an emote.
+
+use std::{
+    collections::VecDeque,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use bytes::{BufMut, BytesMut};
+use futures::{Sink, Stream};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::codec::{Decoder, Encoder, Framed, LinesCodec, LinesCodecError};
+
+use super::{Server, SharedState, Transport, BOT_SUFFIX, LOBBY};
+use crate::codec::{lines_error, Encoding};
+use crate::error::*;
+use crate::message::*;
+use crate::protocol::*;
+
+/// Be lenient with the 512 bytes limit of RFC 1459, since our messages may be longer
+const MAX_LINE_LENGTH: usize = 8192;
+
+/// Reads lines ended with LF or CRLF, and writes them ended with CRLF as RFC 1459 requires
+struct IrcCodec(LinesCodec);
+
+impl Decoder for IrcCodec {
+    type Item = String;
+    type Error = LinesCodecError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> std::result::Result<Option<String>, Self::Error> {
+        self.0.decode(src)
+    }
+
+    fn decode_eof(
+        &mut self,
+        src: &mut BytesMut,
+    ) -> std::result::Result<Option<String>, Self::Error> {
+        self.0.decode_eof(src)
+    }
+}
+
+impl Encoder<String> for IrcCodec {
+    type Error = LinesCodecError;
+
+    fn encode(&mut self, line: String, dst: &mut BytesMut) -> std::result::Result<(), Self::Error> {
+        // a line break inside would start another command
+        let line = line.replace(&['\r', '\n'][..], " ");
+        dst.reserve(line.len() + 2);
+        dst.put(line.as_bytes());
+        dst.put(&b"\r\n"[..]);
+        Ok(())
+    }
+}
+
+/// A parsed IRC line, e.g. `PRIVMSG #lobby :hello world`, the prefix is ignored
+struct IrcLine {
+    command: String,
+    params: Vec<String>,
+}
+
+impl IrcLine {
+    fn parse(line: &str) -> Option<Self> {
+        let mut rest = line.trim_end_matches('\r').trim_start();
+        if rest.starts_with(':') {
+            rest = rest.split_once(' ')?.1.trim_start();
+        }
+
+        let (head, trailing) = match rest.find(" :") {
+            Some(i) => (&rest[..i], Some(&rest[i + 2..])),
+            None => (rest, None),
+        };
+        let mut words = head.split_whitespace();
+        let command = words.next()?.to_uppercase();
+        let mut params: Vec<String> = words.map(str::to_owned).collect();
+        params.extend(trailing.map(str::to_owned));
+
+        Some(Self { command, params })
+    }
+}
+
+/// A connection of an IRC client, translating IRC lines into `ClientCommand`s and
+/// `ServerCommand`s into IRC lines
+///
+/// Commands that don't involve the server (PING, NAMES, ...) are answered right here.
+pub struct IrcTransport {
+    inner: Framed<TcpStream, IrcCodec>,
+    server_name: String,
+    nick: Option<String>,
+    requested_nick: Option<String>, // asked to the server, until it accepts or rejects it
+    user_received: bool,
+    registered: bool,
+    joined: bool,
+    users: Vec<User>,
+    pending: VecDeque<String>, // lines waiting to be written to `inner`
+}
+
+impl IrcTransport {
+    fn new(stream: TcpStream) -> Self {
+        Self {
+            inner: Framed::new(
+                stream,
+                IrcCodec(LinesCodec::new_with_max_length(MAX_LINE_LENGTH)),
+            ),
+            server_name: "chat".to_owned(),
+            nick: None,
+            requested_nick: None,
+            user_received: false,
+            registered: false,
+            joined: false,
+            users: Vec::new(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    fn channel() -> String {
+        format!("#{}", LOBBY)
+    }
+
+    fn nick(&self) -> &str {
+        self.nick.as_deref().unwrap_or("*")
+    }
+
+    /// Queue a numeric reply or a notice from the server
+    fn reply(&mut self, line: String) {
+        let line = format!(":{} {}", self.server_name, line);
+        self.pending.push_back(line);
+    }
+
+    /// Queue a line originated from another user
+    fn relay(&mut self, user: &str, line: String) {
+        let nick = to_nick(user);
+        self.pending
+            .push_back(format!(":{}!{}@{} {}", nick, nick, self.server_name, line));
+    }
+
+    fn reply_names(&mut self) {
+        let names: Vec<_> = self.users.iter().map(|u| to_nick(u)).collect();
+        let (nick, channel) = (self.nick().to_owned(), Self::channel());
+        self.reply(format!("353 {} = {} :{}", nick, channel, names.join(" ")));
+        self.reply(format!("366 {} {} :End of /NAMES list.", nick, channel));
+    }
+
+    /// Handle a line from the client, returning the command to pass to the server if any
+    fn handle_line(&mut self, line: IrcLine) -> Option<ClientCommand> {
+        let nick = self.nick().to_owned();
+        let param = |i: usize| line.params.get(i).cloned();
+
+        match line.command.as_str() {
+            "NICK" => {
+                let new_nick = param(0)?;
+                if new_nick.ends_with(BOT_SUFFIX) || new_nick.contains('@') {
+                    self.reply(format!("432 {} {} :Erroneous nickname", nick, new_nick));
+                    return None;
+                }
+                if self.registered {
+                    if new_nick == nick {
+                        return None;
+                    }
+                    if (self.users.iter())
+                        .any(|u| u != &nick && to_nick(u).eq_ignore_ascii_case(&new_nick))
+                    {
+                        self.reply(format!(
+                            "433 {} {} :Nickname is already in use",
+                            nick, new_nick
+                        ));
+                        return None;
+                    }
+                    // the nick changes once the server echoes the rename
+                    self.requested_nick = Some(new_nick.clone());
+                    return Some(ClientCommand::SetName(new_nick));
+                }
+                self.nick = Some(new_nick);
+                self.register()
+            }
+            "USER" => {
+                self.user_received = true;
+                self.register()
+            }
+            "PING" => {
+                let token = param(0).unwrap_or_default();
+                self.reply(format!("PONG {} :{}", self.server_name, token));
+                None
+            }
+            "PONG" | "CAP" | "MODE" => None,
+            "QUIT" => None, // handled by the caller
+            _ if !self.registered => {
+                self.reply(format!("451 {} :You have not registered", nick));
+                None
+            }
+            "JOIN" => {
+                for channel in param(0)?.split(',') {
+                    if channel.eq_ignore_ascii_case(&Self::channel()) {
+                        self.joined = true;
+                        self.relay(&nick, format!("JOIN {}", Self::channel()));
+                        self.reply_names();
+                    } else {
+                        self.reply(format!("403 {} {} :No such channel", nick, channel));
+                    }
+                }
+                None
+            }
+            "PART" => {
+                for channel in param(0)?.split(',') {
+                    if self.joined && channel.eq_ignore_ascii_case(&Self::channel()) {
+                        self.joined = false;
+                        self.relay(&nick, format!("PART {}", Self::channel()));
+                    } else {
+                        self.reply(format!(
+                            "442 {} {} :You're not on that channel",
+                            nick, channel
+                        ));
+                    }
+                }
+                None
+            }
+            "NAMES" => {
+                self.reply_names();
+                None
+            }
+            "PRIVMSG" | "NOTICE" => {
+                let (target, text) = (param(0)?, param(1)?);
+                let message = match text.strip_prefix("\x01ACTION ") {
+                    Some(action) => Message::Action(action.trim_end_matches('\x01').to_owned()),
+                    None if line.command == "NOTICE" => Message::Notice(text),
+                    None => Message::Text(text),
+                };
+                let user = self.users.iter().find(|u| to_nick(u) == target).cloned();
+                if target.eq_ignore_ascii_case(&Self::channel()) && self.joined {
+                    Some(ClientCommand::SendMessage(message))
+                } else if let Some(user) = user {
+                    Some(ClientCommand::SendDirect(user, message))
+                } else {
+                    self.reply(format!("401 {} {} :No such nick/channel", nick, target));
+                    None
+                }
+            }
+            command => {
+                self.reply(format!("421 {} {} :Unknown command", nick, command));
+                None
+            }
+        }
+    }
+
+    /// Register to the server once both NICK and USER are received
+    fn register(&mut self) -> Option<ClientCommand> {
+        if self.user_received && !self.registered {
+            self.requested_nick = self.nick.clone();
+            self.nick.clone().map(ClientCommand::SetName)
+        } else {
+            None
+        }
+    }
+
+    /// Translate a command from the server into IRC lines
+    fn handle_command(&mut self, command: ServerCommand) {
+        let (nick, channel) = (self.nick().to_owned(), Self::channel());
+
+        match command {
+            ServerCommand::ServerName(name) => {
+                self.server_name = name.replace(' ', "-");
+                if !self.registered {
+                    self.registered = true;
+                    self.requested_nick = None;
+                    let server_name = self.server_name.clone();
+                    self.reply(format!(
+                        "001 {} :Welcome to {}, {}",
+                        nick, server_name, nick
+                    ));
+                    self.reply(format!("002 {} :Your host is {}", nick, server_name));
+                    self.reply(format!("376 {} :Join {} to chat", nick, channel));
+                }
+            }
+            ServerCommand::UserMessage(user, message, _) => {
+                if self.joined && user != nick {
+                    let (command, lines) = irc_lines(&message);
+                    for line in lines {
+                        self.relay(&user, format!("{} {} :{}", command, channel, line));
+                    }
+                }
+            }
+            // IRC clients don't see their own messages echoed
+            ServerCommand::DirectMessage(from, _to, message) => {
+                if from != nick {
+                    let (command, lines) = irc_lines(&message);
+                    for line in lines {
+                        self.relay(&from, format!("{} {} :{}", command, nick, line));
+                    }
+                }
+            }
+            ServerCommand::ServerMessage(message) => {
+                if self.joined {
+                    for line in message.text().lines() {
+                        self.reply(format!("NOTICE {} :{}", channel, line));
+                    }
+                }
+            }
+            ServerCommand::UserList(users) => {
+                self.users = users.into_iter().map(|(user, _addr)| user).collect();
+            }
+            ServerCommand::UserJoined { user, .. } => {
+                if user != nick {
+                    self.users.push(user.clone());
+                    if self.joined {
+                        self.relay(&user, format!("JOIN {}", channel));
+                    }
+                }
+            }
+            ServerCommand::UserLeft { user, .. } => {
+                self.users.retain(|u| *u != user);
+                if self.joined {
+                    self.relay(&user, format!("PART {}", channel));
+                }
+            }
+            // the own nick only changes once the server accepts it
+            ServerCommand::UserRenamed { old, new, .. } => {
+                for user in self.users.iter_mut().filter(|u| **u == old) {
+                    *user = new.clone();
+                }
+                if old == nick {
+                    self.nick = Some(new.clone());
+                    self.requested_nick = None;
+                }
+                if self.joined || old == nick {
+                    self.relay(&old, format!("NICK :{}", to_nick(&new)));
+                }
+            }
+            // the server answers a name with an error when it's taken
+            ServerCommand::Error(_) if self.requested_nick.is_some() => {
+                let requested = self.requested_nick.take().unwrap_or_default();
+                if !self.registered {
+                    // let the client try another nick, as a reply to `*`
+                    self.nick = None;
+                }
+                let nick = self.nick().to_owned();
+                self.reply(format!(
+                    "433 {} {} :Nickname is already in use",
+                    nick, requested
+                ));
+            }
+            ServerCommand::Error(message) => {
+                self.reply(format!("NOTICE {} :Error: {}", nick, message));
+            }
+            // IRC clients don't search nor switch encodings, and relays are only sent to linked servers
+            ServerCommand::SearchResults(..)
+            | ServerCommand::Encoding(_)
+            | ServerCommand::Relay(_) => {}
+        }
+    }
+
+    /// Write as many pending lines as possible into `inner`, then flush it
+    fn poll_write_pending(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        while !self.pending.is_empty() {
+            futures::ready!(Sink::<String>::poll_ready(Pin::new(&mut self.inner), cx))
+                .map_err(lines_error)?;
+            let line = self.pending.pop_front().unwrap();
+            Pin::new(&mut self.inner)
+                .start_send(line)
+                .map_err(lines_error)?;
+        }
+        Sink::<String>::poll_flush(Pin::new(&mut self.inner), cx).map_err(lines_error)
+    }
+}
+
+impl Transport for IrcTransport {
+    fn supports_encoding(&self, _encoding: Encoding) -> bool {
+        false
+    }
+
+    fn set_encoding(&mut self, _encoding: Encoding) {}
+}
+
+impl Stream for IrcTransport {
+    type Item = Result<ClientCommand>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            // replies are written on a best-effort basis, a pending write will wake us up again
+            if let Poll::Ready(Err(e)) = self.poll_write_pending(cx) {
+                return Poll::Ready(Some(Err(e)));
+            }
+
+            let line = match futures::ready!(Pin::new(&mut self.inner).poll_next(cx)) {
+                Some(Ok(line)) => line,
+                Some(Err(e)) => return Poll::Ready(Some(Err(lines_error(e)))),
+                None => return Poll::Ready(None),
+            };
+            let line = match IrcLine::parse(&line) {
+                Some(line) => line,
+                None => continue,
+            };
+            if line.command == "QUIT" {
+                return Poll::Ready(None);
+            }
+            if let Some(command) = self.handle_line(line) {
+                return Poll::Ready(Some(Ok(command)));
+            }
+        }
+    }
+}
+
+impl Sink<ServerCommand> for IrcTransport {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: ServerCommand) -> Result<()> {
+        self.handle_command(item);
+        Ok(())
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.poll_write_pending(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        futures::ready!(self.poll_write_pending(cx))?;
+        Sink::<String>::poll_close(Pin::new(&mut self.inner), cx).map_err(lines_error)
+    }
+}
+
+/// IRC nicks cannot contain spaces, nor the `!`, `@` and `:` of prefixes, e.g. `alice@office`
+/// relayed from a linked server becomes `alice|office`
+fn to_nick(user: &str) -> String {
+    user.replace([' ', '!', ':'], "_").replace('@', "|")
+}
+
+/// The IRC command and the lines telling a message, an emote being a CTCP ACTION
+fn irc_lines(message: &Message) -> (&'static str, Vec<String>) {
+    let lines = message.text().lines();
+    match message {
+        Message::Text(_) => ("PRIVMSG", lines.map(str::to_owned).collect()),
+        Message::Action(_) => {
+            let action = lines.collect::<Vec<_>>().join(" ");
+            ("PRIVMSG", vec![format!("\x01ACTION {}\x01", action)])
+        }
+        Message::Notice(_) | Message::System(_) => ("NOTICE", lines.map(str::to_owned).collect()),
+    }
+}
+
+/// An infinite loop that accepts IRC connections and serves them like tcp ones
+pub(super) async fn accept(listener: &TcpListener, state: SharedState) -> Result<()> {
+    log::info!("listen on {:?} for irc", listener.local_addr()?);
+
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        let arc_state = state.clone();
+        tokio::spawn(async move {
+            let _ = Server::handle(IrcTransport::new(stream), addr, arc_state).await;
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> (String, Vec<String>) {
+        let line = IrcLine::parse(line).unwrap();
+        (line.command, line.params)
+    }
+
+    #[test]
+    fn nicks_fit_in_prefixes() {
+        assert_eq!(to_nick("alice@office"), "alice|office");
+        assert_eq!(to_nick("a b!c:d"), "a_b_c_d");
+        assert_eq!(to_nick("bob[bot]"), "bob[bot]");
+
+        // clients split the prefix at the first `!` and `@`
+        let nick = to_nick(":bob!x@y z");
+        let prefix = format!("{}!{}@chat", nick, nick);
+        let (prefix_nick, rest) = prefix.split_once('!').unwrap();
+        assert_eq!(prefix_nick, nick);
+        assert_eq!(rest.split_once('@').unwrap().0, nick);
+    }
+
+    #[test]
+    fn parse_middle_and_trailing_params() {
+        assert_eq!(
+            parse("PRIVMSG #lobby :hello world"),
+            (
+                "PRIVMSG".to_owned(),
+                vec!["#lobby".to_owned(), "hello world".to_owned()]
+            )
+        );
+        assert_eq!(
+            parse("user guest 0 * :Real Name\r"),
+            (
+                "USER".to_owned(),
+                vec!["guest", "0", "*", "Real Name"]
+                    .into_iter()
+                    .map(str::to_owned)
+                    .collect()
+            )
+        );
+        // a colon inside a middle param doesn't start the trailing one
+        assert_eq!(
+            parse("PING a:b"),
+            ("PING".to_owned(), vec!["a:b".to_owned()])
+        );
+        assert_eq!(
+            parse("PRIVMSG #lobby ::)"),
+            (
+                "PRIVMSG".to_owned(),
+                vec!["#lobby".to_owned(), ":)".to_owned()]
+            )
+        );
+        assert_eq!(
+            parse("PRIVMSG #lobby :"),
+            (
+                "PRIVMSG".to_owned(),
+                vec!["#lobby".to_owned(), String::new()]
+            )
+        );
+    }
+
+    #[test]
+    fn parse_skips_the_prefix() {
+        assert_eq!(
+            parse(":nick!user@host  NICK  other"),
+            ("NICK".to_owned(), vec!["other".to_owned()])
+        );
+        assert!(IrcLine::parse(":prefix-only").is_none());
+        assert!(IrcLine::parse("").is_none());
+    }
+
+    #[test]
+    fn lines_are_written_with_crlf() {
+        let mut codec = IrcCodec(LinesCodec::new());
+        let mut buf = BytesMut::new();
+        codec.encode("PING :a".to_owned(), &mut buf).unwrap();
+        codec
+            .encode("NOTICE x :one\ntwo".to_owned(), &mut buf)
+            .unwrap();
+        assert_eq!(&buf[..], &b"PING :a\r\nNOTICE x :one two\r\n"[..]);
+
+        let mut input = BytesMut::from(&b"NICK a\r\nUSER b\n"[..]);
+        assert_eq!(codec.decode(&mut input).unwrap().as_deref(), Some("NICK a"));
+        assert_eq!(codec.decode(&mut input).unwrap().as_deref(), Some("USER b"));
+    }
+}
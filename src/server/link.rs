@@ -0,0 +1,138 @@
+//! Links between servers, relaying messages of the lobby
+//!
+//! A server links to another one by connecting to its normal port and sending
+//! `ClientCommand::Link` first with the secret both are given, then both sides exchange
+//! `Relay`s of the messages posted on them. Each relay carries an id from its origin server, so that it's posted and forwarded
+//! only once by every server even if the links form a loop.
+
+use std::collections::{HashSet, VecDeque};
+use std::time::Duration;
+
+use futures::SinkExt;
+use tokio::net::TcpStream;
+use tokio::stream::StreamExt;
+use tokio::sync::mpsc;
+use tokio_util::codec::Framed;
+
+use super::{SendPeer, SharedState};
+use crate::codec::ChatCodec;
+use crate::error::*;
+use crate::message::*;
+use crate::protocol::*;
+
+type LinkTransport = Framed<TcpStream, ChatCodec<ServerCommand>>;
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// How many relay ids are remembered to detect loops
+const MAX_SEEN_RELAYS: usize = 4096;
+
+/// A bounded set of the most recently seen relay ids
+#[derive(Default)]
+pub(super) struct SeenRelays {
+    ids: HashSet<RelayId>,
+    order: VecDeque<RelayId>,
+}
+
+impl SeenRelays {
+    /// Remember `id`, return whether it's newly seen
+    pub fn insert(&mut self, id: RelayId) -> bool {
+        if !self.ids.insert(id.clone()) {
+            return false;
+        }
+        self.order.push_back(id);
+        if self.order.len() > MAX_SEEN_RELAYS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+/// An infinite loop that keeps a link to the server at `remote`, reconnecting when it's lost
+pub(super) async fn connect(remote: &str, state: SharedState) -> Result<()> {
+    loop {
+        if let Err(e) = run(remote, state.clone()).await {
+            log::warn!("[link {}] error: {}", remote, e);
+        }
+        log::info!("[link {}] reconnect in {:?}", remote, RECONNECT_DELAY);
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+/// Link to the server at `remote`, relaying messages until the link is lost
+async fn run(remote: &str, state: SharedState) -> Result<()> {
+    let stream = TcpStream::connect(remote).await?;
+    let addr = stream.peer_addr()?;
+    let mut transport: LinkTransport = Framed::new(stream, ChatCodec::new());
+
+    let (server, secret) = {
+        let state = state.lock().await;
+        (
+            state.name.clone(),
+            state.link_secret.clone().unwrap_or_default(),
+        )
+    };
+    transport
+        .send(ClientCommand::Link { server, secret })
+        .await?;
+
+    // register the link as a peer, so that it'll receive relays
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    state.lock().await.peers.insert(
+        addr,
+        SendPeer {
+            tx,
+            username: User::new(),
+            addr,
+            link: Some(remote.to_owned()),
+        },
+    );
+    log::info!("[link {}] connected", remote);
+
+    let result = async {
+        let mut linked = false; // until the remote tells its name
+        loop {
+            tokio::select! {
+                command = transport.next() => match command {
+                    // message relayed from the remote server
+                    Some(Ok(ServerCommand::Relay(relay))) => {
+                        state.lock().await.receive_relay(relay, addr);
+                    }
+                    Some(Ok(ServerCommand::ServerName(server))) => {
+                        log::info!("[link {}] linked to server: {}", remote, server);
+                        linked = true;
+                        if let Some(peer) = state.lock().await.peers.get_mut(&addr) {
+                            peer.link = Some(server);
+                        }
+                    }
+                    // an error before the name is the remote refusing the link
+                    Some(Ok(ServerCommand::Error(e))) if !linked => {
+                        return Err(Error::LinkRefused(e));
+                    }
+                    Some(Ok(ServerCommand::Error(e))) => {
+                        log::warn!("[link {}] remote error: {}", remote, e);
+                    }
+                    Some(Ok(_)) => {}
+                    Some(Err(e)) if e.is_fatal() => return Err(e),
+                    Some(Err(e)) => log::warn!("[link {}] error: {}", remote, e),
+                    None => return Ok(()),
+                },
+                op = rx.recv() => match op {
+                    // message to relay to the remote server
+                    Some(Operation::Relay(relay)) => {
+                        transport.send(ClientCommand::Relay(relay)).await?;
+                    }
+                    Some(Operation::Kick(_)) | None => return Ok(()),
+                    Some(_) => {}
+                },
+            }
+        }
+    }
+    .await;
+
+    state.lock().await.peers.remove(&addr);
+    log::info!("[link {}] disconnected", remote);
+    result
+}
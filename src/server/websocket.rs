@@ -0,0 +1,99 @@
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures::{Sink, Stream};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::{
+    tungstenite::{protocol::WebSocketConfig, Message as WsMessage},
+    WebSocketStream,
+};
+
+use super::{Server, SharedState, Transport};
+use crate::codec::{Encoding, MAX_FRAME_LENGTH};
+use crate::error::*;
+use crate::protocol::*;
+
+/// A websocket connection carrying one json `ClientCommand`/`ServerCommand` per message
+pub struct WsTransport {
+    inner: WebSocketStream<TcpStream>,
+}
+
+impl Transport for WsTransport {
+    fn supports_encoding(&self, encoding: Encoding) -> bool {
+        encoding == Encoding::Json
+    }
+
+    fn set_encoding(&mut self, _encoding: Encoding) {}
+}
+
+impl Stream for WsTransport {
+    type Item = Result<ClientCommand>;
+
+    /// Decode text and binary messages, skipping control messages
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            let message = match futures::ready!(Pin::new(&mut self.inner).poll_next(cx)) {
+                Some(Ok(message)) => message,
+                Some(Err(e)) => return Poll::Ready(Some(Err(e.into()))),
+                None => return Poll::Ready(None),
+            };
+            let command = match message {
+                WsMessage::Text(text) => serde_json::from_str(&text),
+                WsMessage::Binary(bytes) => serde_json::from_slice(&bytes),
+                WsMessage::Close(_) => return Poll::Ready(None),
+                // pings are answered by tungstenite itself
+                WsMessage::Ping(_) | WsMessage::Pong(_) => continue,
+            };
+            return Poll::Ready(Some(command.map_err(Into::into)));
+        }
+    }
+}
+
+impl Sink<ServerCommand> for WsTransport {
+    type Error = Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.inner).poll_ready(cx).map_err(Into::into)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: ServerCommand) -> Result<()> {
+        let text = serde_json::to_string(&item)?;
+        Pin::new(&mut self.inner)
+            .start_send(WsMessage::Text(text))
+            .map_err(Into::into)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx).map_err(Into::into)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx).map_err(Into::into)
+    }
+}
+
+/// An infinite loop that accepts websocket connections and serves them like tcp ones
+pub(super) async fn accept(listener: &TcpListener, state: SharedState) -> Result<()> {
+    log::info!("listen on {:?} for websocket", listener.local_addr()?);
+
+    let config = WebSocketConfig {
+        max_message_size: Some(MAX_FRAME_LENGTH),
+        max_frame_size: Some(MAX_FRAME_LENGTH),
+        ..WebSocketConfig::default()
+    };
+
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        let arc_state = state.clone();
+        tokio::spawn(async move {
+            match tokio_tungstenite::accept_async_with_config(stream, Some(config)).await {
+                Ok(inner) => {
+                    let _ = Server::handle(WsTransport { inner }, addr, arc_state).await;
+                }
+                Err(e) => log::warn!("[{}] websocket handshake failed: {}", addr, e),
+            }
+        });
+    }
+}
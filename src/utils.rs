@@ -1,3 +1,12 @@
+/// Compare secrets in constant time, so that their content can't be guessed from timings
+pub fn secrets_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
 /// Generate a random adj-noun name if the input is empty
 pub fn new_name(name: String) -> String {
     if name.is_empty() {